@@ -0,0 +1,296 @@
+//Assembler for hand written p-code. Reads the listing format of `pl0r disasm`,
+//so listings can be edited and run again:
+//
+//    0  jmp  0    1
+//    1  int  0    3
+//
+//The address column is optional, but if given it must match. Labels end with ':' and
+//can be used as the address field, comments start with ';'. Instructions that always
+//have level 0 (lit, opr, int, jmp, jpc) can be written with the address field only:
+//
+//start:  int 3         ; room for static link, dynamic link, return address
+//        lit 5
+//        opr 15        ; write
+//        opr 0         ; return
+
+use crate::defs::{fct_from_i32, Fct, Instruction, Program, MNEMONICS};
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+use std::collections::HashMap;
+
+//Words of one line with their spans, comment left out
+fn words(text: &str, line: i32, offset: usize) -> Vec<(&str, Span)> {
+    let text = match text.find(';') {
+        Some(i) => &text[..i],
+        None => text,
+    };
+    let mut out = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                let col = text[..s].chars().count() as i32 + 1;
+                let span = Span {
+                    line,
+                    col,
+                    start: offset + s,
+                    end: offset + i,
+                };
+                out.push((&text[s..i], span));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Asm<'a> {
+    name: Option<&'a str>,
+    prog: Program,
+    labels: HashMap<String, i32>,
+    fixups: Vec<(usize, String, Span)>, //Label references resolved after the last line
+    diags: Vec<Diagnostic>,
+}
+
+fn error(a: &mut Asm, span: Span, message: &str) {
+    a.diags
+        .push(Diagnostic::new(a.name, Some(span), 0, message));
+}
+
+fn number(a: &mut Asm, word: (&str, Span)) -> i32 {
+    match word.0.parse::<i32>() {
+        Ok(n) => n,
+        Err(_) => {
+            error(a, word.1, "number expected");
+            0
+        }
+    }
+}
+
+fn line(a: &mut Asm, mut words: &[(&str, Span)]) {
+    let cx = a.prog.code.len();
+
+    //Optional address column from a listing
+    if words.len() > 1 && words[0].0.chars().all(|c| c.is_ascii_digit()) {
+        if words[0].0.parse::<usize>() != Ok(cx) {
+            let msg = format!("address {} out of sequence, expected {cx}", words[0].0);
+            error(a, words[0].1, &msg);
+        }
+        words = &words[1..];
+    }
+
+    //Optional label
+    if let Some((word, span)) = words.first() {
+        if let Some(label) = word.strip_suffix(':') {
+            if !is_label(label) {
+                error(a, *span, "bad label name");
+            } else if a.labels.insert(label.to_owned(), cx as i32).is_some() {
+                error(a, *span, &format!("label {label} defined twice"));
+            }
+            words = &words[1..];
+        }
+    }
+
+    let (mnemonic, span) = match words.first() {
+        Some(w) => *w,
+        None => return,
+    };
+    let fct = match MNEMONICS
+        .iter()
+        .position(|m| m.eq_ignore_ascii_case(mnemonic))
+    {
+        Some(ind) => fct_from_i32(ind as i32).unwrap(),
+        None => {
+            error(a, span, &format!("unknown instruction {mnemonic}"));
+            return;
+        }
+    };
+    let level_fixed = matches!(fct, Fct::Lit | Fct::Opr | Fct::Int | Fct::Jmp | Fct::Jpc);
+    let (level, adr) = match &words[1..] {
+        [adr] if level_fixed => (0, *adr),
+        [level, adr] => (number(a, *level), *adr),
+        [] | [_] => {
+            error(a, span, "level and address expected");
+            return;
+        }
+        [_, _, extra, ..] => {
+            error(a, extra.1, "end of line expected");
+            return;
+        }
+    };
+    let adr = if is_label(adr.0) {
+        a.fixups.push((cx, adr.0.to_owned(), adr.1));
+        0
+    } else {
+        number(a, adr)
+    };
+    a.prog.code.push(Instruction { fct, level, adr });
+    a.prog.lines.push(span.line);
+}
+
+//Assemble p-code text into a program, name is used in diagnostics
+pub fn assemble(text: &str, name: Option<&str>) -> Result<Program, Vec<Diagnostic>> {
+    let mut a = Asm {
+        name,
+        prog: Program {
+            code: Vec::new(),
+            entry: 0,
+            lines: Vec::new(),
+            name: name.map(|n| n.to_owned()),
+            scopes: Vec::new(),
+            stmts: Vec::new(),
+        },
+        labels: HashMap::new(),
+        fixups: Vec::new(),
+        diags: Vec::new(),
+    };
+
+    let mut offset = 0;
+    for (i, text) in text.split_inclusive('\n').enumerate() {
+        let w = words(text, i as i32 + 1, offset);
+        line(&mut a, &w);
+        offset += text.len();
+    }
+
+    for (cx, label, span) in std::mem::take(&mut a.fixups) {
+        match a.labels.get(&label) {
+            Some(adr) => a.prog.code[cx].adr = *adr,
+            None => error(&mut a, span, &format!("undefined label {label}")),
+        }
+    }
+    if a.prog.code.is_empty() && a.diags.is_empty() {
+        let span = Span {
+            line: 1,
+            col: 1,
+            start: 0,
+            end: 0,
+        };
+        error(&mut a, span, "program has no instructions");
+    }
+
+    a.diags.sort_by_key(|d| d.span.map_or(0, |s| s.start));
+    if a.diags.is_empty() {
+        Ok(a.prog)
+    } else {
+        Err(a.diags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::VecIo;
+
+    const COUNTDOWN: &str = "\
+start:  int 4
+        opr 14          ; read n
+        sto 0 3
+loop:   lod 0 3
+        jpc done
+        lod 0 3
+        opr 15          ; write n, left on the stack
+        lit 1
+        opr 3
+        sto 0 3
+        jmp loop
+done:   opr 0
+";
+
+    //(line, col, message) of each diagnostic
+    fn errors(text: &str) -> Vec<(i32, i32, String)> {
+        let diags = assemble(text, Some("t.asm")).unwrap_err();
+        diags
+            .iter()
+            .map(|d| {
+                assert_eq!(d.file.as_deref(), Some("t.asm"));
+                let span = d.span.unwrap();
+                (span.line, span.col, d.message.clone())
+            })
+            .collect()
+    }
+
+    fn error(line: i32, col: i32, message: &str) -> (i32, i32, String) {
+        (line, col, message.to_owned())
+    }
+
+    #[test]
+    fn runs_assembled_program() {
+        let prog = assemble(COUNTDOWN, None).unwrap();
+        assert_eq!(prog.code.len(), 12);
+        assert_eq!(
+            prog.code[4],
+            Instruction {
+                fct: Fct::Jpc,
+                level: 0,
+                adr: 11
+            }
+        );
+        assert_eq!(
+            prog.code[10],
+            Instruction {
+                fct: Fct::Jmp,
+                level: 0,
+                adr: 3
+            }
+        );
+        assert_eq!(prog.lines[4], 5);
+        let mut io = VecIo::new(&[3]);
+        crate::run(&prog, &mut io).unwrap();
+        assert_eq!(io.output, [3, 2, 1]);
+    }
+
+    #[test]
+    fn reads_listing() {
+        let listing = "    0  jmp  0    1\n    1  int  0    3\n    2  lit  0    7\n    3  opr  0   15\n    4  opr  0    0\n";
+        let prog = assemble(listing, None).unwrap();
+        let mut io = VecIo::new(&[]);
+        crate::run(&prog, &mut io).unwrap();
+        assert_eq!(io.output, [7]);
+    }
+
+    #[test]
+    fn bad_instructions() {
+        assert_eq!(
+            errors("  mov 0 1\nlit 1x\nlod 3\nlod 0 3 4\n"),
+            [
+                error(1, 3, "unknown instruction mov"),
+                error(2, 5, "number expected"),
+                error(3, 1, "level and address expected"),
+                error(4, 9, "end of line expected"),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_labels() {
+        assert_eq!(
+            errors("a: jmp b\na: opr 0\n1x: opr 0\njmp c\n"),
+            [
+                error(1, 8, "undefined label b"),
+                error(2, 1, "label a defined twice"),
+                error(3, 1, "bad label name"),
+                error(4, 5, "undefined label c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_addresses() {
+        assert_eq!(
+            errors("0 int 0 3\n2 opr 0 0\n"),
+            [error(2, 1, "address 2 out of sequence, expected 1")]
+        );
+        assert_eq!(
+            errors("; nothing here\n"),
+            [error(1, 1, "program has no instructions")]
+        );
+    }
+}
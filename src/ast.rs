@@ -0,0 +1,134 @@
+//Abstract syntax tree, built by the parser and walked by code generation and tools.
+//Every node carries the span of its source text, from its first to its last symbol.
+//After a syntax error the parser still returns a tree, with placeholders (empty
+//statements, number 0) where something was missing.
+
+use crate::token::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub block: Block,
+    pub span: Span,
+    pub comments: Vec<Span>, //Comments in source order, they are not part of the tree
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub decls: Vec<Decl>, //In source order
+    pub body: Stmt,
+    pub span: Span,
+    pub end: Span, //Last symbol of the block, where it returns
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclKind {
+    Const(Ident, i32, Span), //Name, value and the span of the value
+    Var(Ident),
+    Procedure(Ident, Box<Block>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub kind: DeclKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Assign(Ident, Expr),
+    Call(Ident),
+    Read(Ident),
+    Write(Expr),
+    Begin(Vec<Stmt>),
+    If(Cond, Box<Stmt>, Option<(Span, Box<Stmt>)>), //Condition, then, else keyword and branch
+    While(Cond, Box<Stmt>),
+    Repeat(Vec<Stmt>, Cond),
+    Case {
+        expr: Expr,
+        arms: Vec<CaseArm>,
+        otherwise: Option<(Span, Box<Stmt>)>, //else keyword and branch
+    },
+    For {
+        var: Ident,
+        from: Expr,
+        down: bool, //downto instead of to
+        to: Expr,
+        by: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    Empty,
+}
+
+//Labels of a case arm, a single value or a range low..high
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseLabel {
+    pub low: Expr,
+    pub high: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseArm {
+    pub labels: Vec<CaseLabel>,
+    pub body: Stmt,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CondKind {
+    Odd(Expr),
+    Compare(RelOp, Expr, Expr),
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cond {
+    pub kind: CondKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(i32),
+    Name(Ident),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
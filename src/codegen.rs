@@ -0,0 +1,691 @@
+//Code generation: walks the syntax tree and emits p-code, checking names on the way
+//with Wirth's identifier table. The table is a stack, the names of a block are
+//dropped when its code is done.
+
+use crate::ast::*;
+use crate::defs::*;
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum ObjType {
+    Constant,
+    Variable,
+    Procedure,
+}
+
+#[derive(Clone, Debug)]
+struct ObjDesc {
+    name: String,
+    kind: ObjType,
+    val_or_lev: i32, //Value of a constant, level of a variable or procedure
+    adr: i32,        //Frame offset of a variable, entry address of a procedure
+}
+
+pub struct CodeGen<'a> {
+    name: Option<&'a str>,      //Source name for diagnostics
+    table: Vec<ObjDesc>,        //Identifier table, innermost names last
+    code: Vec<Instruction>,     //Generated code
+    lines: Vec<i32>,            //Source line of every generated instruction
+    line: i32,                  //Line of the node being generated
+    scopes: Vec<Scope>,         //Blocks with their declarations, for debuggers
+    scope: Option<usize>,       //Index of the block being generated in scopes
+    stmts: Vec<i32>,            //Address where each statement's code begins
+    temp: i32,                  //Frame offset of the next hidden temporary, for loop limits
+    frame: i32,                 //Frame size of the block body: variables and temporaries
+    pub diags: Vec<Diagnostic>, //Errors found during code generation
+}
+
+impl<'a> CodeGen<'a> {
+    pub fn new(name: Option<&'a str>) -> Self {
+        CodeGen {
+            name,
+            table: Vec::new(),
+            code: Vec::with_capacity(CODE_ARR_SIZE as usize),
+            lines: Vec::with_capacity(CODE_ARR_SIZE as usize),
+            line: 1,
+            scopes: Vec::new(),
+            scope: None,
+            stmts: Vec::new(),
+            temp: 0,
+            frame: 0,
+            diags: Vec::new(),
+        }
+    }
+}
+
+fn gen_error(g: &mut CodeGen, span: Span, n: usize) {
+    let d = Diagnostic::new(g.name, Some(span), n, ERR_MSGS[n]);
+    g.diags.push(d);
+}
+
+fn cx(g: &CodeGen) -> i32 {
+    g.code.len() as i32
+}
+
+fn gen(g: &mut CodeGen, x: Fct, y: i32, z: i32, span: Span) {
+    if g.code.len() >= CODE_ARR_SIZE as usize {
+        if g.code.len() == CODE_ARR_SIZE as usize && !g.diags.iter().any(|d| d.code == 34) {
+            gen_error(g, span, 34);
+        }
+        return;
+    }
+    g.code.push(Instruction {
+        fct: x,
+        level: y,
+        adr: z,
+    });
+    g.lines.push(g.line);
+}
+
+//Backpatch the address field of a jump, unless the program was too long to hold it
+fn fixup(g: &mut CodeGen, at: i32, adr: i32) {
+    if let Some(instr) = g.code.get_mut(at as usize) {
+        instr.adr = adr;
+    }
+}
+
+fn enter(g: &mut CodeGen, name: &Ident, kind: ObjType, val_or_lev: i32, adr: i32) {
+    g.table.push(ObjDesc {
+        name: name.name.clone(),
+        kind,
+        val_or_lev,
+        adr,
+    });
+}
+
+//Innermost declaration of a name, reports error 11 if there is none
+fn position(g: &mut CodeGen, id: &Ident) -> Option<ObjDesc> {
+    match g.table.iter().rev().find(|obj| obj.name == id.name) {
+        Some(obj) => Some(obj.clone()),
+        None => {
+            gen_error(g, id.span, 11);
+            None
+        }
+    }
+}
+
+//Value of a constant expression, None if it has variables or does not compute
+fn constant(g: &mut CodeGen, e: &Expr) -> Option<i32> {
+    match &e.kind {
+        ExprKind::Number(n) => Some(*n),
+        ExprKind::Name(id) => match position(g, id) {
+            Some(v) if v.kind == ObjType::Constant => Some(v.val_or_lev),
+            _ => None,
+        },
+        ExprKind::Neg(x) => constant(g, x)?.checked_neg(),
+        ExprKind::Binary(op, l, r) => {
+            let (l, r) = (constant(g, l)?, constant(g, r)?);
+            match op {
+                BinOp::Add => l.checked_add(r),
+                BinOp::Sub => l.checked_sub(r),
+                BinOp::Mul => l.checked_mul(r),
+                BinOp::Div => l.checked_div(r),
+            }
+        }
+    }
+}
+
+//Values of the labels of a case arm as ranges, with errors for labels that are not
+//constants, empty ranges and values already used by an earlier label
+fn case_labels(
+    g: &mut CodeGen,
+    arm: &CaseArm,
+    seen: &mut Vec<(i32, i32)>,
+) -> Vec<(i32, i32, Span)> {
+    let mut out = Vec::new();
+    for label in &arm.labels {
+        let low = constant(g, &label.low);
+        let high = match &label.high {
+            Some(e) => constant(g, e),
+            None => low,
+        };
+        let (low, high) = match (low, high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => {
+                gen_error(g, label.span, 44);
+                continue;
+            }
+        };
+        if low.unsigned_abs() > ADDR_MAX as u32 || high.unsigned_abs() > ADDR_MAX as u32 {
+            gen_error(g, label.span, 30);
+        } else if low > high {
+            gen_error(g, label.span, 46);
+        } else if seen.iter().any(|(l, h)| low <= *h && *l <= high) {
+            gen_error(g, label.span, 45);
+        } else {
+            seen.push((low, high));
+            out.push((low, high, label.span));
+        }
+    }
+    out
+}
+
+//Push a number, negative ones as lit and neg
+fn literal(g: &mut CodeGen, n: i32, span: Span) {
+    gen(g, Fct::Lit, 0, n.abs(), span);
+    if n < 0 {
+        gen(g, Fct::Opr, 0, 1, span);
+    }
+}
+
+fn expression(g: &mut CodeGen, lev: i32, e: &Expr) {
+    g.line = e.span.line;
+    match &e.kind {
+        ExprKind::Number(n) => {
+            let mut num = *n;
+            if num > ADDR_MAX {
+                gen_error(g, e.span, 30);
+                num = 0;
+            }
+            gen(g, Fct::Lit, 0, num, e.span);
+        }
+        ExprKind::Name(id) => {
+            if let Some(v) = position(g, id) {
+                match v.kind {
+                    ObjType::Constant => gen(g, Fct::Lit, 0, v.val_or_lev, e.span),
+                    ObjType::Variable => gen(g, Fct::Lod, lev - v.val_or_lev, v.adr, e.span),
+                    ObjType::Procedure => gen_error(g, id.span, 21),
+                }
+            }
+        }
+        ExprKind::Neg(x) => {
+            expression(g, lev, x);
+            gen(g, Fct::Opr, 0, 1, e.span);
+        }
+        ExprKind::Binary(op, l, r) => {
+            expression(g, lev, l);
+            expression(g, lev, r);
+            let n = match op {
+                BinOp::Add => 2,
+                BinOp::Sub => 3,
+                BinOp::Mul => 4,
+                BinOp::Div => 5,
+            };
+            gen(g, Fct::Opr, 0, n, e.span);
+        }
+    }
+}
+
+//Code for c that jumps when c is jump_if and falls through otherwise, returns the jumps
+//for the caller to fix up. and and or skip their right side when the left decides.
+//Every comparison ends in its own jpc, falling through when it is true, so that
+//coverage shows how each one went.
+fn condition(g: &mut CodeGen, lev: i32, c: &Cond, jump_if: bool) -> Vec<i32> {
+    match &c.kind {
+        CondKind::Odd(_) | CondKind::Compare(..) => {
+            comparison(g, lev, c);
+            let at = cx(g);
+            gen(g, Fct::Jpc, 0, 0, c.span);
+            if !jump_if {
+                return vec![at];
+            }
+            let jump = cx(g);
+            gen(g, Fct::Jmp, 0, 0, c.span);
+            fixup(g, at, cx(g));
+            vec![jump]
+        }
+        CondKind::Not(x) => condition(g, lev, x, !jump_if),
+        //a and b is false when a is, a or b is true when a is
+        CondKind::And(l, r) | CondKind::Or(l, r) => {
+            let decides = matches!(c.kind, CondKind::Or(..));
+            let mut left = condition(g, lev, l, decides);
+            let right = condition(g, lev, r, jump_if);
+            if jump_if == decides {
+                left.extend(right);
+                return left;
+            }
+            for at in left {
+                fixup(g, at, cx(g));
+            }
+            right
+        }
+    }
+}
+
+//Value of odd or a comparison on the stack
+fn comparison(g: &mut CodeGen, lev: i32, c: &Cond) {
+    match &c.kind {
+        CondKind::Odd(e) => {
+            expression(g, lev, e);
+            gen(g, Fct::Opr, 0, 6, c.span);
+        }
+        CondKind::Compare(op, l, r) => {
+            expression(g, lev, l);
+            expression(g, lev, r);
+            let n = match op {
+                RelOp::Eq => 8,
+                RelOp::Ne => 9,
+                RelOp::Lt => 10,
+                RelOp::Ge => 11,
+                RelOp::Gt => 12,
+                RelOp::Le => 13,
+            };
+            gen(g, Fct::Opr, 0, n, c.span);
+        }
+        _ => {}
+    }
+}
+
+fn statement(g: &mut CodeGen, lev: i32, s: &Stmt) {
+    let begin_or_empty = matches!(s.kind, StmtKind::Begin(_) | StmtKind::Empty);
+    if !begin_or_empty && g.stmts.last() != Some(&cx(g)) {
+        g.stmts.push(cx(g));
+    }
+    g.line = s.span.line;
+    match &s.kind {
+        StmtKind::Assign(id, e) => {
+            let v = position(g, id);
+            if let Some(v) = &v {
+                if v.kind != ObjType::Variable {
+                    //Assignment to non-variable
+                    gen_error(g, id.span, 12);
+                }
+            }
+            expression(g, lev, e);
+            g.line = s.span.line;
+            if let Some(v) = v.filter(|v| v.kind == ObjType::Variable) {
+                gen(g, Fct::Sto, lev - v.val_or_lev, v.adr, s.span);
+            }
+        }
+        StmtKind::Call(id) => {
+            if let Some(v) = position(g, id) {
+                match v.kind {
+                    ObjType::Procedure => gen(g, Fct::Cal, lev - v.val_or_lev, v.adr, s.span),
+                    _ => gen_error(g, id.span, 15),
+                }
+            }
+        }
+        StmtKind::Read(id) => {
+            if let Some(v) = position(g, id) {
+                match v.kind {
+                    ObjType::Variable => {
+                        gen(g, Fct::Opr, 0, 14, s.span);
+                        gen(g, Fct::Sto, lev - v.val_or_lev, v.adr, s.span);
+                    }
+                    _ => gen_error(g, id.span, 27),
+                }
+            }
+        }
+        StmtKind::Write(e) => {
+            expression(g, lev, e);
+            gen(g, Fct::Opr, 0, 15, s.span);
+        }
+        StmtKind::Begin(stmts) => {
+            for st in stmts {
+                statement(g, lev, st);
+            }
+        }
+        StmtKind::If(c, then, otherwise) => {
+            let jumps = condition(g, lev, c, false);
+            statement(g, lev, then);
+            match otherwise {
+                Some((_, otherwise)) => {
+                    //The then branch jumps over the else branch
+                    let cx2 = cx(g);
+                    g.line = s.span.line;
+                    gen(g, Fct::Jmp, 0, 0, s.span);
+                    for at in jumps {
+                        fixup(g, at, cx(g));
+                    }
+                    statement(g, lev, otherwise);
+                    fixup(g, cx2, cx(g));
+                }
+                None => {
+                    for at in jumps {
+                        fixup(g, at, cx(g));
+                    }
+                }
+            }
+        }
+        StmtKind::While(c, body) => {
+            let cx1 = cx(g);
+            let jumps = condition(g, lev, c, false);
+            statement(g, lev, body);
+            g.line = s.span.line;
+            gen(g, Fct::Jmp, 0, cx1, s.span);
+            for at in jumps {
+                fixup(g, at, cx(g));
+            }
+        }
+        StmtKind::Repeat(stmts, c) => {
+            //Back to the first statement while the condition is false
+            let cx1 = cx(g);
+            for st in stmts {
+                statement(g, lev, st);
+            }
+            for at in condition(g, lev, c, false) {
+                fixup(g, at, cx1);
+            }
+        }
+        StmtKind::Case {
+            expr,
+            arms,
+            otherwise,
+        } => {
+            //The selector is evaluated once, into a temporary of the frame
+            let t = g.temp;
+            g.temp += 1;
+            g.frame = g.frame.max(g.temp);
+            expression(g, lev, expr);
+            g.line = s.span.line;
+            gen(g, Fct::Sto, 0, t, s.span);
+
+            let mut seen: Vec<(i32, i32)> = Vec::new();
+            let mut ends = Vec::new(); //Jumps from the end of each arm to the end of the case
+            for (i, arm) in arms.iter().enumerate() {
+                let labels = case_labels(g, arm, &mut seen);
+                let mut bodies = Vec::new(); //Jumps from a matching label to the arm's body
+                let mut next = Vec::new(); //Jumps from a label that does not match
+                if labels.is_empty() {
+                    next.push(cx(g));
+                    gen(g, Fct::Jmp, 0, 0, arm.span);
+                }
+                for (j, (low, high, span)) in labels.iter().enumerate() {
+                    //A label falls through when it matches, so coverage counts matches as
+                    //true. All but the last jump to the body, a miss tries the next label.
+                    for at in next.drain(..) {
+                        fixup(g, at, cx(g));
+                    }
+                    g.line = span.line;
+                    gen(g, Fct::Lod, 0, t, *span);
+                    literal(g, *low, *span);
+                    if low == high {
+                        gen(g, Fct::Opr, 0, 8, *span); //=
+                        next.push(cx(g));
+                        gen(g, Fct::Jpc, 0, 0, *span);
+                    } else {
+                        gen(g, Fct::Opr, 0, 11, *span); //>=
+                        next.push(cx(g));
+                        gen(g, Fct::Jpc, 0, 0, *span);
+                        gen(g, Fct::Lod, 0, t, *span);
+                        literal(g, *high, *span);
+                        gen(g, Fct::Opr, 0, 13, *span); //<=
+                        next.push(cx(g));
+                        gen(g, Fct::Jpc, 0, 0, *span);
+                    }
+                    if j + 1 < labels.len() {
+                        bodies.push(cx(g));
+                        gen(g, Fct::Jmp, 0, 0, *span);
+                    }
+                }
+                for at in bodies {
+                    fixup(g, at, cx(g));
+                }
+                statement(g, lev, &arm.body);
+                if i < arms.len() - 1 || otherwise.is_some() {
+                    g.line = s.span.line;
+                    ends.push(cx(g));
+                    gen(g, Fct::Jmp, 0, 0, s.span);
+                }
+                for at in next {
+                    fixup(g, at, cx(g));
+                }
+            }
+            if let Some((_, otherwise)) = otherwise {
+                statement(g, lev, otherwise);
+            }
+            for at in ends {
+                fixup(g, at, cx(g));
+            }
+            g.temp -= 1;
+        }
+        StmtKind::For {
+            var,
+            from,
+            down,
+            to,
+            by,
+            body,
+        } => {
+            let v = position(g, var);
+            if v.as_ref().is_some_and(|v| v.kind != ObjType::Variable) {
+                gen_error(g, var.span, 40);
+            }
+            let (l, a) = v.map_or((0, 0), |v| (lev - v.val_or_lev, v.adr));
+            let step = match by {
+                Some(e) => match constant(g, e) {
+                    Some(n) if n > 0 => n,
+                    _ => {
+                        gen_error(g, e.span, 41);
+                        1
+                    }
+                },
+                None => 1,
+            };
+            expression(g, lev, from);
+            g.line = s.span.line;
+            gen(g, Fct::Sto, l, a, s.span);
+
+            //The limit is evaluated once, into a temporary of the frame
+            let limit = g.temp;
+            g.temp += 1;
+            g.frame = g.frame.max(g.temp);
+            expression(g, lev, to);
+            g.line = s.span.line;
+            gen(g, Fct::Sto, 0, limit, s.span);
+
+            let cx1 = cx(g);
+            gen(g, Fct::Lod, l, a, s.span);
+            gen(g, Fct::Lod, 0, limit, s.span);
+            gen(g, Fct::Opr, 0, if *down { 11 } else { 13 }, s.span); //>= or <=
+            let cx2 = cx(g);
+            gen(g, Fct::Jpc, 0, 0, s.span);
+            statement(g, lev, body);
+            g.line = s.span.line;
+            gen(g, Fct::Lod, l, a, s.span);
+            gen(g, Fct::Lit, 0, step, s.span);
+            gen(g, Fct::Opr, 0, if *down { 3 } else { 2 }, s.span);
+            gen(g, Fct::Sto, l, a, s.span);
+            gen(g, Fct::Jmp, 0, cx1, s.span);
+            fixup(g, cx2, cx(g));
+            g.temp -= 1;
+        }
+        StmtKind::Empty => {}
+    }
+}
+
+//Code of a block, proc is the table index of its procedure, None for the main program
+fn block(g: &mut CodeGen, lev: i32, proc: Option<usize>, b: &Block) {
+    let mut dx: i32 = 3; //data allocation index
+    let tx0 = g.table.len(); //names of this block start here
+    let parent = g.scope;
+    let sx = g.scopes.len(); //scope index of this block
+
+    g.line = b.span.line;
+    let start = cx(g);
+    g.scopes.push(Scope {
+        name: proc.map_or(String::new(), |i| g.table[i].name.clone()),
+        level: lev,
+        parent,
+        start,
+        entry: start,
+        end: start,
+        vars: Vec::new(),
+        consts: Vec::new(),
+    });
+    g.scope = Some(sx);
+    if let Some(i) = proc {
+        g.table[i].adr = start;
+    }
+    gen(g, Fct::Jmp, 0, 0, b.span);
+    if lev > MAX_BLOCK_NESTING {
+        gen_error(g, b.span, 32);
+    }
+
+    for decl in &b.decls {
+        match &decl.kind {
+            DeclKind::Const(id, n, value) => {
+                if *n > ADDR_MAX {
+                    gen_error(g, *value, 30);
+                }
+                enter(g, id, ObjType::Constant, *n, 0);
+                g.scopes[sx].consts.push((id.name.clone(), *n));
+            }
+            DeclKind::Var(id) => {
+                enter(g, id, ObjType::Variable, lev, dx);
+                g.scopes[sx].vars.push((id.name.clone(), dx));
+                dx += 1;
+            }
+            DeclKind::Procedure(id, body) => {
+                enter(g, id, ObjType::Procedure, lev, 0);
+                let i = g.table.len() - 1;
+                block(g, lev + 1, Some(i), body);
+            }
+        }
+    }
+
+    fixup(g, start, cx(g));
+    if let Some(i) = proc {
+        g.table[i].adr = cx(g);
+    }
+    g.scopes[sx].entry = cx(g);
+    g.line = b.body.span.line;
+    let int_at = cx(g);
+    gen(g, Fct::Int, 0, dx, b.body.span);
+    g.temp = dx;
+    g.frame = dx;
+    statement(g, lev, &b.body);
+    fixup(g, int_at, g.frame); //Room for the temporaries too
+    g.line = b.end.line;
+    gen(g, Fct::Opr, 0, 0, b.end); //return
+    g.scopes[sx].end = cx(g);
+    g.scope = parent;
+    g.table.truncate(tx0);
+}
+
+//Generate code for a parsed program, errors are left in g.diags
+pub fn codegen_run(g: &mut CodeGen, prog: &crate::ast::Program) {
+    block(g, 0, None, &prog.block);
+}
+
+//Compiled code of a code generation run
+pub fn program(g: &CodeGen) -> crate::defs::Program {
+    crate::defs::Program {
+        code: g.code.clone(),
+        entry: 0,
+        lines: g.lines.clone(),
+        name: g.name.map(|n| n.to_owned()),
+        scopes: g.scopes.clone(),
+        stmts: g.stmts.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InterpreterConfig, VecIo};
+
+    //Output of a program, a jump to the wrong place stops it with an error, not a hang
+    fn run(src: &str, input: &[i32]) -> Vec<i32> {
+        let prog = crate::compile(src).unwrap();
+        let config = InterpreterConfig {
+            max_steps: Some(10_000),
+            ..InterpreterConfig::default()
+        };
+        let mut io = VecIo::new(input);
+        crate::run_with(&prog, &config, &mut io).unwrap();
+        io.output
+    }
+
+    type Check = fn(i32, i32, i32) -> bool;
+
+    //Conditions on a, b and c with their meaning. not turns the jumps of what it
+    //negates around, so and and or are generated both ways.
+    const CONDITIONS: &[(&str, Check)] = &[
+        ("a > 0 and b > 0", |a, b, _| a > 0 && b > 0),
+        ("a > 0 or b > 0", |a, b, _| a > 0 || b > 0),
+        ("not (a > 0)", |a, _, _| a <= 0),
+        ("not not (a = 1)", |a, _, _| a == 1),
+        ("not (a > 0 and b > 0)", |a, b, _| !(a > 0 && b > 0)),
+        ("not (a > 0 or b > 0)", |a, b, _| !(a > 0 || b > 0)),
+        ("a = 1 or b = 1 and c = 1", |a, b, c| {
+            a == 1 || (b == 1 && c == 1)
+        }),
+        ("(a = 1 or b = 1) and c = 1", |a, b, c| {
+            (a == 1 || b == 1) && c == 1
+        }),
+        ("a > 0 and (b > 0 or not (c > 0))", |a, b, c| {
+            a > 0 && (b > 0 || c <= 0)
+        }),
+        ("not (a = 1 or not (b = 1 and c <> 2))", |a, b, c| {
+            !(a == 1 || !(b == 1 && c != 2))
+        }),
+        (
+            "not (a = 0 and b = 0) and not (c = 0 or a = 2)",
+            |a, b, c| (a != 0 || b != 0) && c != 0 && a != 2,
+        ),
+        ("odd a or odd b and not odd c", |a, b, c| {
+            a % 2 != 0 || (b % 2 != 0 && c % 2 == 0)
+        }),
+    ];
+
+    #[test]
+    fn boolean_operators() {
+        for (cond, check) in CONDITIONS {
+            //In if and while the condition jumps when false, in repeat it is the left
+            //side of an or that jumps when true
+            let src = format!(
+                "var a, b, c, k;\nbegin\n  ? a; ? b; ? c;\n  if {cond} then ! 1 else ! 0;\n  \
+                 k := 0;\n  while ({cond}) and k = 0 do k := 1;\n  ! k;\n  \
+                 k := 0;\n  repeat k := k + 1 until ({cond}) or k = 2;\n  ! k\nend."
+            );
+            for a in 0..3 {
+                for b in 0..3 {
+                    for c in 0..3 {
+                        let t = check(a, b, c);
+                        let expected = vec![t as i32, t as i32, if t { 1 } else { 2 }];
+                        let out = run(&src, &[a, b, c]);
+                        assert_eq!(out, expected, "{cond} with a={a}, b={b}, c={c}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_circuit() {
+        let src = "var j;\nbegin\n  j := 0;\n  if j # 0 and 10 / j > 1 then ! 1 else ! 0;\n  \
+                   if j = 0 or 10 / j > 1 then ! 1 else ! 0;\n  \
+                   if not (j = 0 or 10 / j > 1) then ! 1 else ! 0;\n  \
+                   while j # 0 and 10 / j > 1 do j := 0;\n  ! 2\nend.";
+        assert_eq!(run(src, &[]), vec![0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn parenthesised_conditions() {
+        let src = "var a, b;\nbegin\n  ? a; ? b;\n  if ((a) > b) then ! 1 else ! 0;\n  \
+                   if (a + 1) * 2 > 7 then ! 1 else ! 0;\n  \
+                   if ((a)) + 1 > b then ! 1 else ! 0;\n  \
+                   if ((a + 1) * 2 > 7 and (b) > 0) then ! 1 else ! 0;\n  \
+                   if (not (a > b) or ((a - b) * (a - b) = 0)) then ! 1 else ! 0\nend.";
+        for a in 0..5 {
+            for b in 0..5 {
+                let expected = [
+                    a > b,
+                    (a + 1) * 2 > 7,
+                    a + 1 > b,
+                    (a + 1) * 2 > 7 && b > 0,
+                    a <= b || a == b,
+                ];
+                let expected: Vec<i32> = expected.iter().map(|t| *t as i32).collect();
+                assert_eq!(run(src, &[a, b]), expected, "a={a}, b={b}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_branch_before_else() {
+        let src = "\
+var a;
+begin
+  ? a;
+  if a = 1 then else ! 10;
+  case a of 1: else ! 20 end;
+  case a of 1: ! 31; 2: else ! 30 end
+end.
+";
+        assert_eq!(run(src, &[1]), [31]);
+        assert_eq!(run(src, &[2]), [10, 20]);
+        assert_eq!(run(src, &[3]), [10, 20, 30]);
+    }
+}
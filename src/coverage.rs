@@ -0,0 +1,185 @@
+//Source coverage from the instruction counts of a profiled run. Lines are found with
+//the line table, branches are the jpc instructions of conditions and case labels: the
+//test was true when jpc fell through and false when it jumped.
+
+use crate::defs::{Fct, Program};
+use crate::profile::Profile;
+
+//Lines that have code and their executions: the most executed of their instructions.
+//The jmp starting a block is left out, a call enters the block after it.
+fn line_counts(prog: &Program, profile: &Profile) -> Vec<(i32, u64)> {
+    let mut lines: Vec<(i32, u64)> = Vec::new();
+    for (adr, line) in prog.lines.iter().enumerate() {
+        if prog.scopes.iter().any(|sc| sc.start == adr as i32) {
+            continue;
+        }
+        let n = profile.counts.get(adr).copied().unwrap_or(0);
+        match lines.iter_mut().find(|(l, _)| l == line) {
+            Some((_, max)) => *max = (*max).max(n),
+            None => lines.push((*line, n)),
+        }
+    }
+    lines.sort_by_key(|(line, _)| *line);
+    lines
+}
+
+//Every jpc with its line, times its condition was true and times it was false
+fn branches(prog: &Program, profile: &Profile) -> Vec<(usize, i32, u64, u64)> {
+    let mut out = Vec::new();
+    for (adr, instr) in prog.code.iter().enumerate() {
+        if let (Fct::Jpc, Some(line)) = (instr.fct, prog.lines.get(adr)) {
+            let n = profile.counts.get(adr).copied().unwrap_or(0);
+            let taken = profile.taken.get(adr).copied().unwrap_or(0);
+            out.push((adr, *line, n - taken, taken));
+        }
+    }
+    out
+}
+
+fn ratio(hit: usize, all: usize) -> String {
+    if all == 0 {
+        format!("{hit}/{all}")
+    } else {
+        format!("{hit}/{all} ({:.1}%)", hit as f64 * 100.0 / all as f64)
+    }
+}
+
+//Source listing with execution counts, gcov style: '-' for lines without code and
+//'#####' for lines never executed, every branch below its line
+pub fn coverage_listing(source: &str, prog: &Program, profile: &Profile) -> String {
+    let lines = line_counts(prog, profile);
+    let branches = branches(prog, profile);
+    let mut out = String::new();
+    let mut text: Vec<&str> = source.lines().collect();
+    let last = lines.last().map_or(0, |(line, _)| *line);
+    while (text.len() as i32) < last {
+        text.push("");
+    }
+    for (i, text) in text.iter().enumerate() {
+        let line = i as i32 + 1;
+        let count = match lines.iter().find(|(l, _)| *l == line) {
+            Some((_, 0)) => "#####".to_owned(),
+            Some((_, n)) => n.to_string(),
+            None => "-".to_owned(),
+        };
+        out.push_str(&format!("{count:>9}:{line:>5}: {text}\n"));
+        for (adr, _, yes, no) in branches.iter().filter(|b| b.1 == line) {
+            let shown = |n: u64| match n {
+                0 => "never".to_owned(),
+                n => n.to_string(),
+            };
+            out.push_str(&format!(
+                "{:>16} {adr}: true {}, false {}\n",
+                "branch",
+                shown(*yes),
+                shown(*no)
+            ));
+        }
+    }
+    let hit = lines.iter().filter(|(_, n)| *n > 0).count();
+    let directions = branches.len() * 2;
+    let covered: usize = branches
+        .iter()
+        .map(|(_, _, yes, no)| (*yes > 0) as usize + (*no > 0) as usize)
+        .sum();
+    out.push_str(&format!(
+        "\nLines: {}, branches: {}\n",
+        ratio(hit, lines.len()),
+        ratio(covered, directions)
+    ));
+    out
+}
+
+//Coverage in the lcov tracefile format, for genhtml and CI tools
+pub fn coverage_lcov(prog: &Program, profile: &Profile) -> String {
+    let count = |adr: i32| profile.counts.get(adr as usize).copied().unwrap_or(0);
+    let mut out = String::from("TN:\n");
+    out.push_str(&format!(
+        "SF:{}\n",
+        prog.name.as_deref().unwrap_or("<source>")
+    ));
+
+    let mut hit = 0;
+    for sc in &prog.scopes {
+        let name = if sc.name.is_empty() {
+            "<main>"
+        } else {
+            &sc.name
+        };
+        let line = prog.lines.get(sc.start as usize).copied().unwrap_or(0);
+        out.push_str(&format!("FN:{line},{name}\n"));
+        out.push_str(&format!("FNDA:{},{name}\n", count(sc.entry)));
+        hit += (count(sc.entry) > 0) as usize;
+    }
+    out.push_str(&format!("FNF:{}\nFNH:{hit}\n", prog.scopes.len()));
+
+    let branches = branches(prog, profile);
+    let mut hit = 0;
+    for (block, (adr, line, yes, no)) in branches.iter().enumerate() {
+        for (branch, n) in [*yes, *no].iter().enumerate() {
+            let taken = match count(*adr as i32) {
+                0 => "-".to_owned(),
+                _ => n.to_string(),
+            };
+            out.push_str(&format!("BRDA:{line},{block},{branch},{taken}\n"));
+            hit += (*n > 0) as usize;
+        }
+    }
+    out.push_str(&format!("BRF:{}\nBRH:{hit}\n", branches.len() * 2));
+
+    let lines = line_counts(prog, profile);
+    for (line, n) in &lines {
+        out.push_str(&format!("DA:{line},{n}\n"));
+    }
+    let hit = lines.iter().filter(|(_, n)| *n > 0).count();
+    out.push_str(&format!("LF:{}\nLH:{hit}\n", lines.len()));
+    out.push_str("end_of_record\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::InterpreterConfig;
+    use crate::io::VecIo;
+
+    fn profiled(src: &str, input: &[i32]) -> (Program, Profile) {
+        let prog = crate::compile(src).unwrap();
+        let mut profile = Profile::default();
+        let config = InterpreterConfig::default();
+        crate::run_profiled(&prog, &config, &mut VecIo::new(input), &mut profile).unwrap();
+        (prog, profile)
+    }
+
+    #[test]
+    fn empty_branch() {
+        //The jpc of an empty then jumps to the next address
+        let src = "var x;\nbegin\n  ? x;\n  if x = 1 then ;\n  ! x\nend.\n";
+        let (prog, profile) = profiled(src, &[0]);
+        assert_eq!(prog.code[7].fct, Fct::Jpc);
+        assert_eq!(prog.code[7].adr, 8);
+        assert_eq!(profile.taken[7], 1);
+        assert_eq!(branches(&prog, &profile), [(7, 4, 0, 1)]);
+        let listing = coverage_listing(src, &prog, &profile);
+        assert!(
+            listing.contains("    branch 7: true never, false 1\n"),
+            "{listing}"
+        );
+
+        let (prog, profile) = profiled(src, &[1]);
+        assert_eq!(profile.taken[7], 0);
+        assert_eq!(branches(&prog, &profile), [(7, 4, 1, 0)]);
+    }
+
+    #[test]
+    fn loop_branches() {
+        let src =
+            "var x;\nbegin\n  ? x;\n  while x > 0 do x := x - 1;\n  if odd x then ! 1\nend.\n";
+        let (prog, profile) = profiled(src, &[3]);
+        let found: Vec<(i32, u64, u64)> = branches(&prog, &profile)
+            .into_iter()
+            .map(|(_, line, t, f)| (line, t, f))
+            .collect();
+        assert_eq!(found, [(4, 3, 1), (5, 0, 1)]);
+    }
+}
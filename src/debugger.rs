@@ -0,0 +1,648 @@
+//Interactive debugger for p-code, reads commands line by line:
+//
+//  break LINE | break @ADR   stop at a source line or a code address
+//  step, next                run to the next statement, next steps over calls
+//  stepi, nexti              run one instruction, nexti steps over cal
+//  frames                    activation records with static and dynamic links
+//  print NAME                value of a variable or constant visible here
+//
+//`?` of the debugged program reads its number from the same input after a "? " prompt.
+
+use crate::defs::{Fct, Program};
+use crate::interpreter::{base, step, InterpreterConfig, Machine, RuntimeError};
+use crate::io::{Io, IoError};
+use crate::listing::instr_listing;
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "(pl0r) ";
+const LIST_LINES: i32 = 5; //Source lines shown around the current line
+
+const HELP: &str = "\
+break LINE, break @ADR   set breakpoint at source line or code address (b)
+delete [N]               delete breakpoint N, or all of them (d)
+breaks                   list breakpoints
+run                      start the program again and run to a breakpoint
+continue                 run to the next breakpoint or the end (c)
+step, next               run to the next statement, next steps over calls (s, n)
+stepi, nexti             run one instruction, nexti steps over cal (si, ni)
+finish                   run until the current procedure returns
+registers                p, b and t registers (r)
+frames                   activation records with static and dynamic links (bt)
+stack                    stack contents from the bottom to t
+print NAME               value of a variable or constant visible here (p)
+locals                   variables of the current procedure
+list [LINE]              source around the current or given line (l)
+code [ADR]               instructions around the current or given address (x)
+help                     this text (h)
+quit                     leave the debugger (q)
+Empty line repeats the last command.";
+
+#[derive(PartialEq, Copy, Clone)]
+enum State {
+    Running,
+    Halted, //Main program returned
+    Failed, //Stopped by a runtime error
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum Mode {
+    Instr,    //One instruction
+    Line,     //To the start of the next statement
+    Continue, //To a breakpoint
+    Finish,   //Until the current procedure returns
+}
+
+struct Breakpoint {
+    spec: String, //As given: line number or @address
+    adr: i32,     //Address where it stops
+}
+
+struct Debugger<'a> {
+    prog: &'a Program,
+    source: Vec<&'a str>,
+    config: InterpreterConfig,
+    m: Machine<'a>,
+    state: State,
+    breaks: Vec<Option<Breakpoint>>, //Numbered from 1, deleted ones are None
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+//Program input and output go through the debugger session
+struct SessionIo<'b> {
+    input: &'b mut dyn BufRead,
+    output: &'b mut dyn Write,
+}
+
+impl Io for SessionIo<'_> {
+    fn read_int(&mut self) -> Result<i32, IoError> {
+        write!(self.output, "? ")?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(IoError::EndOfInput);
+        }
+        let text = line.trim();
+        text.parse::<i32>()
+            .map_err(|_| IoError::InvalidInput(text.to_owned()))
+    }
+
+    fn write_int(&mut self, n: i32) -> Result<(), IoError> {
+        writeln!(self.output, "{n}")?;
+        Ok(())
+    }
+}
+
+fn line_of(d: &Debugger, adr: i32) -> Option<i32> {
+    d.prog.lines.get(adr as usize).copied()
+}
+
+//Address of the first statement on the line, or of the line's first instruction when
+//statements are not known
+fn line_start(prog: &Program, line: i32) -> Option<i32> {
+    let adr = (0..prog.lines.len()).find(|a| {
+        prog.lines[*a] == line
+            && (prog.stmts.is_empty() || prog.stmts.binary_search(&(*a as i32)).is_ok())
+    })?;
+    Some(adr as i32)
+}
+
+fn scope_name(prog: &Program, adr: i32) -> String {
+    match prog.scope_at(adr) {
+        Some(sx) if !prog.scopes[sx].name.is_empty() => prog.scopes[sx].name.clone(),
+        Some(_) => "<main>".to_owned(),
+        None => "?".to_owned(),
+    }
+}
+
+//Stack slot if it is inside the stack
+fn slot(d: &Debugger, adr: i32) -> Option<i32> {
+    if adr < 0 {
+        return None;
+    }
+    d.m.s.get(adr as usize).copied()
+}
+
+fn show_location(d: &mut Debugger) -> io::Result<()> {
+    let pc = d.m.p;
+    if let Some(line) = line_of(d, pc) {
+        if let Some(text) = d.source.get((line - 1) as usize) {
+            writeln!(d.output, "{line:>5} | {text}")?;
+        }
+    }
+    let instr = d.prog.code[pc as usize];
+    writeln!(
+        d.output,
+        "=> {}    in {}",
+        instr_listing(pc as usize, &instr),
+        scope_name(d.prog, pc)
+    )
+}
+
+fn resume(d: &mut Debugger, mode: Mode, over: bool) -> io::Result<()> {
+    if d.state != State::Running {
+        return writeln!(
+            d.output,
+            "program is not running, use run to start it again"
+        );
+    }
+    //Without debug info every instruction is a statement
+    let mode = if mode == Mode::Line && d.prog.stmts.is_empty() {
+        Mode::Instr
+    } else {
+        mode
+    };
+    let start_depth = d.m.depth;
+    loop {
+        let mut io = SessionIo {
+            input: &mut *d.input,
+            output: &mut *d.output,
+        };
+        if let Err(e) = step(&mut d.m, &mut io) {
+            d.state = State::Failed;
+            writeln!(d.output, "runtime error: {e}")?;
+            return show_location(d);
+        }
+        if d.m.halted {
+            d.state = State::Halted;
+            return writeln!(
+                d.output,
+                "program finished after {} instructions",
+                d.m.steps
+            );
+        }
+        let outside = !over || d.m.depth <= start_depth;
+        let stop = match mode {
+            Mode::Instr => outside,
+            Mode::Line => outside && d.prog.stmts.binary_search(&d.m.p).is_ok(),
+            Mode::Continue => false,
+            Mode::Finish => d.m.depth < start_depth,
+        };
+        if stop {
+            break;
+        }
+        let hit = d.breaks.iter().position(|bp| match bp {
+            Some(bp) => bp.adr == d.m.p,
+            None => false,
+        });
+        if let Some(n) = hit {
+            writeln!(d.output, "breakpoint {}", n + 1)?;
+            break;
+        }
+    }
+    show_location(d)
+}
+
+fn restart(d: &mut Debugger) -> io::Result<()> {
+    match Machine::new(d.prog, &d.config) {
+        Ok(m) => {
+            d.m = m;
+            d.state = State::Running;
+            resume(d, Mode::Continue, false)
+        }
+        Err(e) => writeln!(d.output, "runtime error: {e}"),
+    }
+}
+
+fn set_break(d: &mut Debugger, arg: Option<&str>) -> io::Result<()> {
+    let spec = match arg {
+        Some(spec) => spec,
+        None => return writeln!(d.output, "break needs a line number or @address"),
+    };
+    let adr = match spec.strip_prefix('@') {
+        Some(adr) => match adr.parse::<i32>() {
+            Ok(adr) if adr >= 0 && (adr as usize) < d.prog.code.len() => adr,
+            _ => return writeln!(d.output, "no instruction at address {adr}"),
+        },
+        None => match spec.parse::<i32>().map(|line| line_start(d.prog, line)) {
+            Ok(Some(adr)) => adr,
+            Ok(None) => return writeln!(d.output, "no code for line {spec}"),
+            Err(_) => return writeln!(d.output, "bad line number {spec}"),
+        },
+    };
+    d.breaks.push(Some(Breakpoint {
+        spec: spec.to_owned(),
+        adr,
+    }));
+    writeln!(
+        d.output,
+        "breakpoint {} at {spec}, address {adr}",
+        d.breaks.len()
+    )
+}
+
+fn delete_break(d: &mut Debugger, arg: Option<&str>) -> io::Result<()> {
+    match arg.map(|a| a.parse::<usize>()) {
+        None => {
+            d.breaks.iter_mut().for_each(|bp| *bp = None);
+            writeln!(d.output, "all breakpoints deleted")
+        }
+        Some(Ok(n)) if n >= 1 && n <= d.breaks.len() && d.breaks[n - 1].is_some() => {
+            d.breaks[n - 1] = None;
+            writeln!(d.output, "breakpoint {n} deleted")
+        }
+        Some(_) => writeln!(d.output, "no such breakpoint"),
+    }
+}
+
+fn list_breaks(d: &mut Debugger) -> io::Result<()> {
+    let mut any = false;
+    for (n, bp) in d.breaks.iter().enumerate() {
+        if let Some(bp) = bp {
+            writeln!(d.output, "{:>3}  {:<8} address {}", n + 1, bp.spec, bp.adr)?;
+            any = true;
+        }
+    }
+    if !any {
+        writeln!(d.output, "no breakpoints")?;
+    }
+    Ok(())
+}
+
+fn registers(d: &mut Debugger) -> io::Result<()> {
+    writeln!(
+        d.output,
+        "p {}  b {}  t {}  (calls active {}, instructions executed {})",
+        d.m.p, d.m.b, d.m.t, d.m.depth, d.m.steps
+    )
+}
+
+//Variables of a frame, those above t are not allocated yet
+fn frame_vars(d: &mut Debugger, sx: usize, b: i32) -> io::Result<()> {
+    for (name, adr) in &d.prog.scopes[sx].vars {
+        match slot(d, b + adr) {
+            Some(v) if (b + adr) as usize <= d.m.t => writeln!(d.output, "      {name} = {v}")?,
+            _ => writeln!(d.output, "      {name} (not allocated)")?,
+        }
+    }
+    Ok(())
+}
+
+//Activation records from the current one down, following the dynamic links
+fn frames(d: &mut Debugger) -> io::Result<()> {
+    if d.state == State::Halted {
+        return writeln!(d.output, "program is not running");
+    }
+    let mut b = d.m.b;
+    let mut adr = d.m.p;
+    for n in 0..d.m.s.len() {
+        let (sl, dl, ra) = match (slot(d, b), slot(d, b + 1), slot(d, b + 2)) {
+            (Some(sl), Some(dl), Some(ra)) => (sl, dl, ra),
+            _ => return writeln!(d.output, "#{n} bad frame base {b}"),
+        };
+        writeln!(
+            d.output,
+            "#{n} {:<10} base {b:<4} static link {sl:<4} dynamic link {dl:<4} return {ra}",
+            scope_name(d.prog, adr)
+        )?;
+        if let Some(sx) = d.prog.scope_at(adr) {
+            frame_vars(d, sx, b)?;
+        }
+        if b <= 1 || dl <= 0 {
+            break;
+        }
+        b = dl;
+        adr = ra - 1; //The cal instruction in the caller
+    }
+    Ok(())
+}
+
+fn stack(d: &mut Debugger) -> io::Result<()> {
+    //Frame bases on the dynamic chain, to mark link slots
+    let mut bases = Vec::new();
+    let mut b = d.m.b;
+    while b >= 1 && (b as usize) < d.m.s.len() && !bases.contains(&b) {
+        bases.push(b);
+        b = d.m.s[b as usize + 1];
+    }
+    for i in 1..=d.m.t.min(d.m.s.len() - 1) {
+        let i32_i = i as i32;
+        let note = match bases.iter().find(|b| (**b..=**b + 2).contains(&i32_i)) {
+            Some(b) if i32_i == *b => "  <- frame base, static link",
+            Some(b) if i32_i == *b + 1 => "  dynamic link",
+            Some(_) => "  return address",
+            None => "",
+        };
+        writeln!(d.output, "{i:>5}: {}{note}", d.m.s[i])?;
+    }
+    Ok(())
+}
+
+//Look a name up like the parser does: current block first, then the enclosing ones
+fn print(d: &mut Debugger, arg: Option<&str>) -> io::Result<()> {
+    let name = match arg {
+        Some(name) => name,
+        None => return writeln!(d.output, "print needs a name"),
+    };
+    let mut sc = d.prog.scope_at(d.m.p);
+    let mut b = d.m.b;
+    let mut level = 0;
+    while let Some(sx) = sc {
+        let scope = &d.prog.scopes[sx];
+        if let Some((_, v)) = scope.consts.iter().find(|(n, _)| n == name) {
+            return writeln!(d.output, "{name} = {v} (constant)");
+        }
+        if let Some((_, adr)) = scope.vars.iter().find(|(n, _)| n == name) {
+            return match slot(d, b + adr) {
+                Some(v) => writeln!(
+                    d.output,
+                    "{name} = {v} (level {level} down, frame {b}, offset {adr})"
+                ),
+                None => writeln!(d.output, "{name} is outside the stack"),
+            };
+        }
+        b = match base(d.m.p, 1, b, &d.m.s) {
+            Ok(b) => b,
+            Err(_) => break,
+        };
+        sc = scope.parent;
+        level += 1;
+    }
+    if d.prog.scopes.is_empty() {
+        writeln!(d.output, "no names known, program has no debug info")
+    } else {
+        writeln!(d.output, "no variable or constant {name} here")
+    }
+}
+
+fn locals(d: &mut Debugger) -> io::Result<()> {
+    match d.prog.scope_at(d.m.p) {
+        Some(sx) => {
+            writeln!(d.output, "{}", scope_name(d.prog, d.m.p))?;
+            let b = d.m.b;
+            frame_vars(d, sx, b)
+        }
+        None => writeln!(d.output, "no names known here"),
+    }
+}
+
+fn list(d: &mut Debugger, arg: Option<&str>) -> io::Result<()> {
+    let current = line_of(d, d.m.p);
+    let center = match arg.map(|a| a.parse::<i32>()) {
+        Some(Ok(line)) => line,
+        Some(Err(_)) => return writeln!(d.output, "bad line number"),
+        None => match current {
+            Some(line) => line,
+            None => return writeln!(d.output, "no line table"),
+        },
+    };
+    if d.source.is_empty() {
+        return writeln!(d.output, "no source");
+    }
+    let first = (center - LIST_LINES).max(1);
+    let last = (center + LIST_LINES).min(d.source.len() as i32);
+    for line in first..=last {
+        let mark = if Some(line) == current { "=>" } else { "  " };
+        writeln!(
+            d.output,
+            "{mark}{line:>5} | {}",
+            d.source[(line - 1) as usize]
+        )?;
+    }
+    Ok(())
+}
+
+fn code(d: &mut Debugger, arg: Option<&str>) -> io::Result<()> {
+    let center = match arg.map(|a| a.trim_start_matches('@').parse::<i32>()) {
+        Some(Ok(adr)) => adr,
+        Some(Err(_)) => return writeln!(d.output, "bad address"),
+        None => d.m.p,
+    };
+    let first = (center - LIST_LINES).max(0);
+    let last = (center + LIST_LINES).min(d.prog.code.len() as i32 - 1);
+    for adr in first..=last {
+        let mark = if adr == d.m.p { "=>" } else { "  " };
+        let instr = d.prog.code[adr as usize];
+        let call = match instr.fct {
+            Fct::Cal => format!("    ; {}", scope_name(d.prog, instr.adr)),
+            _ => String::new(),
+        };
+        writeln!(
+            d.output,
+            "{mark}{}{call}",
+            instr_listing(adr as usize, &instr)
+        )?;
+    }
+    Ok(())
+}
+
+//Run one command line, false when the session ends
+fn command(d: &mut Debugger, line: &str) -> io::Result<bool> {
+    let mut words = line.split_whitespace();
+    let cmd = match words.next() {
+        Some(cmd) => cmd,
+        None => return Ok(true),
+    };
+    let arg = words.next();
+    match cmd {
+        "break" | "b" => set_break(d, arg)?,
+        "delete" | "d" => delete_break(d, arg)?,
+        "breaks" => list_breaks(d)?,
+        "run" => restart(d)?,
+        "continue" | "c" => resume(d, Mode::Continue, false)?,
+        "step" | "s" => resume(d, Mode::Line, false)?,
+        "next" | "n" => resume(d, Mode::Line, true)?,
+        "stepi" | "si" => resume(d, Mode::Instr, false)?,
+        "nexti" | "ni" => resume(d, Mode::Instr, true)?,
+        "finish" => resume(d, Mode::Finish, false)?,
+        "registers" | "r" => registers(d)?,
+        "frames" | "bt" => frames(d)?,
+        "stack" => stack(d)?,
+        "print" | "p" => print(d, arg)?,
+        "locals" => locals(d)?,
+        "list" | "l" => list(d, arg)?,
+        "code" | "x" => code(d, arg)?,
+        "help" | "h" => writeln!(d.output, "{HELP}")?,
+        "quit" | "q" => return Ok(false),
+        _ => writeln!(d.output, "unknown command {cmd}, try help")?,
+    }
+    Ok(true)
+}
+
+fn session(d: &mut Debugger) -> io::Result<()> {
+    writeln!(d.output, "pl0r debugger, type help for commands")?;
+    show_location(d)?;
+    let mut last = String::new();
+    loop {
+        write!(d.output, "{PROMPT}")?;
+        d.output.flush()?;
+        let mut line = String::new();
+        if d.input.read_line(&mut line)? == 0 {
+            writeln!(d.output)?;
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            line = last.clone();
+        } else {
+            last = line.clone();
+        }
+        if !command(d, &line)? {
+            return Ok(());
+        }
+    }
+}
+
+//Debug a program, commands are read from input and everything is written to output
+pub fn debug<'a>(
+    prog: &'a Program,
+    source: &'a str,
+    config: &InterpreterConfig,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let m = Machine::new(prog, config)?;
+    let mut d = Debugger {
+        prog,
+        source: source.lines().collect(),
+        config: *config,
+        m,
+        state: State::Running,
+        breaks: Vec::new(),
+        input,
+        output,
+    };
+    session(&mut d).map_err(|e| RuntimeError::Io(d.m.p, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACT: &str = "\
+var n, r;
+procedure fact;
+  var k;
+begin
+  k := n;
+  if k > 1 then
+  begin
+    n := n - 1;
+    call fact
+  end;
+  r := r * k
+end;
+begin
+  ? n;
+  r := 1;
+  call fact;
+  ! r
+end.
+";
+
+    //Everything the debugger writes for the commands in `input`, prompts left out
+    fn session(input: &str) -> Vec<String> {
+        let prog = crate::compile(FACT).unwrap();
+        let mut input = input.as_bytes();
+        let mut output = Vec::new();
+        debug(
+            &prog,
+            FACT,
+            &InterpreterConfig::default(),
+            &mut input,
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .split(PROMPT)
+            .flat_map(|s| s.lines())
+            .map(|s| s.trim_end().to_owned())
+            .collect()
+    }
+
+    fn contains(out: &[String], lines: &[&str]) {
+        let found = out.windows(lines.len()).any(|w| w == lines);
+        assert!(found, "{lines:#?} not in {out:#?}");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let out = session(
+            "break 11\nbreak @9\nbreaks\ncontinue\n4\ncontinue\ndelete 2\nbreaks\nc\nc\nc\nc\nc\n",
+        );
+        contains(
+            &out,
+            &[
+                "breakpoint 1 at 11, address 14",
+                "breakpoint 2 at @9, address 9",
+            ],
+        );
+        contains(
+            &out,
+            &["  1  11       address 14", "  2  @9       address 9"],
+        );
+        contains(
+            &out,
+            &[
+                "? breakpoint 2",
+                "    8 |     n := n - 1;",
+                "=>     9  lod  1    3    in fact",
+            ],
+        );
+        contains(&out, &["breakpoint 2 deleted", "  1  11       address 14"]);
+        contains(
+            &out,
+            &[
+                "breakpoint 1",
+                "   11 |   r := r * k",
+                "=>    14  lod  1    4    in fact",
+            ],
+        );
+        contains(&out, &["24", "program finished after 73 instructions"]);
+        let hits = out.iter().filter(|l| l.ends_with("breakpoint 1")).count();
+        assert_eq!(hits, 4);
+        let hits = out.iter().filter(|l| l.ends_with("breakpoint 2")).count();
+        assert_eq!(hits, 2);
+    }
+
+    #[test]
+    fn bad_breakpoints() {
+        let out = session("break\nbreak 99\nbreak x\ndelete 3\nfoo\nq\n");
+        contains(
+            &out[3..],
+            &[
+                "break needs a line number or @address",
+                "no code for line 99",
+                "bad line number x",
+                "no such breakpoint",
+                "unknown command foo, try help",
+            ],
+        );
+        assert_eq!(out.len(), 8);
+    }
+
+    #[test]
+    fn stepping() {
+        let out = session("step\nstep\n3\nstep\nnext\nstepi\nregisters\nstep\nstep\n");
+        contains(
+            &out,
+            &["   14 |   ? n;", "=>    20  opr  0   14    in <main>"],
+        );
+        contains(&out, &["?    15 |   r := 1;"]);
+        contains(&out, &["   16 |   call fact;"]);
+        //next runs the whole call
+        contains(
+            &out,
+            &["   17 |   ! r", "=>    25  lod  0    4    in <main>"],
+        );
+        contains(&out, &["=>    26  opr  0   15    in <main>"]);
+        contains(
+            &out,
+            &["p 26  b 1  t 6  (calls active 0, instructions executed 54)"],
+        );
+        contains(&out, &["6", "program finished after 56 instructions"]);
+        contains(&out, &["program is not running, use run to start it again"]);
+    }
+
+    #[test]
+    fn printing() {
+        let out =
+            session("break 11\nc\n3\nprint k\nprint n\np r\nprint zz\nprint\nfinish\nprint k\nq\n");
+        contains(&out, &["k = 1 (level 0 down, frame 14, offset 3)"]);
+        contains(&out, &["n = 1 (level 1 down, frame 1, offset 3)"]);
+        contains(&out, &["r = 1 (level 1 down, frame 1, offset 4)"]);
+        contains(&out, &["no variable or constant zz here"]);
+        contains(&out, &["print needs a name"]);
+        contains(&out, &["k = 2 (level 0 down, frame 10, offset 3)"]);
+    }
+}
@@ -1,12 +1,13 @@
 pub const EX_NOINPUT: i32 = 66;
 pub const EX_USAGE: i32 = 64;
 pub const EX_DATAERR: i32 = 65;
+pub const EX_SOFTWARE: i32 = 70; //PL/0 program stopped by a runtime error
+pub const EX_IOERR: i32 = 74;
 
-pub const ID_TABLE_LEN: i32 = 100; //Length of identifier table
 pub const ADDR_MAX: i32 = 2047; //Maximum address
 pub const MAX_BLOCK_NESTING: i32 = 3; //Maximum depth of block nesting.
 pub const CODE_ARR_SIZE: i32 = 2047; //Size of code array
-pub const NUM_ERR_MSGS: i32 = 33;
+pub const NUM_ERR_MSGS: i32 = 47;
 pub const ERR_MSGS: [&str; NUM_ERR_MSGS as usize] = [
     "", //empty to accommodate same numbers as pascal implementation
     //1
@@ -48,6 +49,20 @@ pub const ERR_MSGS: [&str; NUM_ERR_MSGS as usize] = [
     //31
     "",
     "Block nesting too deep",
+    "Unexpected character",
+    "Program too long",
+    "Comment is not terminated",
+    "No if for this else, there must be no semicolon before else",
+    "Semicolon or until expected",
+    "for must be followed by an identifier",
+    "to or downto expected",
+    "The variable of a for loop must be a variable",
+    "The step of a for loop must be a positive constant",
+    "of expected",
+    "Colon expected after case labels",
+    "A case label must be a constant",
+    "Duplicate case label",
+    "Case label range is empty",
 ];
 
 //Lit 0, a: Load constant a
@@ -58,7 +73,7 @@ pub const ERR_MSGS: [&str; NUM_ERR_MSGS as usize] = [
 //Int 0, a: Increment t (top of stack) register by a
 //Jmp 0, a: Jump to a
 //Jpc 0, a: Jump conditional to a
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Fct {
     Lit,
     Opr,
@@ -70,7 +85,7 @@ pub enum Fct {
     Jpc,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Instruction {
     pub fct: Fct,   //Function code
     pub level: i32, //Nesting level
@@ -80,3 +95,71 @@ pub struct Instruction {
 pub const NUM_INSTRUCTIONS: usize = 8;
 pub const MNEMONICS: [&str; NUM_INSTRUCTIONS] =
     ["lit", "opr", "lod", "sto", "cal", "int", "jmp", "jpc"];
+
+pub fn fct_as_i32(f: Fct) -> i32 {
+    match f {
+        Fct::Lit => 0,
+        Fct::Opr => 1,
+        Fct::Lod => 2,
+        Fct::Sto => 3,
+        Fct::Cal => 4,
+        Fct::Int => 5,
+        Fct::Jmp => 6,
+        Fct::Jpc => 7,
+    }
+}
+
+pub fn fct_from_i32(n: i32) -> Option<Fct> {
+    match n {
+        0 => Some(Fct::Lit),
+        1 => Some(Fct::Opr),
+        2 => Some(Fct::Lod),
+        3 => Some(Fct::Sto),
+        4 => Some(Fct::Cal),
+        5 => Some(Fct::Int),
+        6 => Some(Fct::Jmp),
+        7 => Some(Fct::Jpc),
+        _ => None,
+    }
+}
+
+//Language options for the scanner. The default is Wirth's PL/0 with comments and with
+//<=, >=, <> and != accepted besides [, ] and #.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    pub nested_comments: bool, //{ } and (* *) comments may contain other comments
+    pub classic_relops: bool,  //Only the one character relational operators [, ] and #
+}
+
+//Debug info: main program or a procedure and the names declared in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub name: String,               //Procedure name, empty for the main program
+    pub level: i32,                 //Nesting level of the body
+    pub parent: Option<usize>,      //Enclosing scope, None for the main program
+    pub start: i32,                 //Address of the block's first instruction (jmp)
+    pub entry: i32,                 //Address called by cal (int)
+    pub end: i32,                   //Address after the block's last instruction
+    pub vars: Vec<(String, i32)>,   //Variables and their offsets in the frame
+    pub consts: Vec<(String, i32)>, //Constants and their values
+}
+
+//Compiled program, ready for the interpreter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub code: Vec<Instruction>, //Generated p-code
+    pub entry: i32,             //Address where execution starts
+    pub lines: Vec<i32>,        //Debug info: source line of every instruction, may be empty
+    pub name: Option<String>,   //Debug info: source name
+    pub scopes: Vec<Scope>,     //Debug info: blocks in source order, may be empty
+    pub stmts: Vec<i32>,        //Debug info: addresses where statements begin, may be empty
+}
+
+impl Program {
+    //Innermost scope containing the address, nested blocks come after their parents
+    pub fn scope_at(&self, adr: i32) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rposition(|sc| sc.start <= adr && adr < sc.end)
+    }
+}
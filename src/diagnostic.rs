@@ -0,0 +1,149 @@
+use crate::json::{quote, quote_opt};
+use crate::token::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+//One error found by scanner, parser or interpreter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>, //Source name, None for anonymous snippets
+    pub span: Option<Span>,   //Location of the offending symbol, None if not known
+    pub code: usize,          //Index into ERR_MSGS, 0 if message is not from the table
+    pub message: String,      //Human readable message
+}
+
+impl Diagnostic {
+    pub fn new(file: Option<&str>, span: Option<Span>, code: usize, message: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            file: file.map(|f| f.to_owned()),
+            span,
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+//Span of the text on a source line, for errors that know only the line
+pub fn line_span(source: &str, line: i32) -> Span {
+    let mut start = 0;
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        if i as i32 + 1 == line {
+            let trimmed = text.trim();
+            let lead = text.len() - text.trim_start().len();
+            return Span {
+                line,
+                col: text[..lead].chars().count() as i32 + 1,
+                start: start + lead,
+                end: start + lead + trimmed.len(),
+            };
+        }
+        start += text.len();
+    }
+    Span {
+        line,
+        col: 1,
+        start: source.len(),
+        end: source.len(),
+    }
+}
+
+fn location(d: &Diagnostic) -> String {
+    let file = d.file.as_deref().unwrap_or("<source>");
+    match d.span {
+        Some(span) => format!("{file}:{}:{}", span.line, span.col),
+        None => file.to_owned(),
+    }
+}
+
+fn headline(d: &Diagnostic) -> String {
+    if d.code == 0 {
+        format!("{}: {}", d.severity, d.message)
+    } else {
+        format!("{}[E{:02}]: {}", d.severity, d.code, d.message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", location(self), headline(self))
+    }
+}
+
+//Diagnostic with the offending source line and the symbol underlined, rustc style:
+//
+//error[E11]: Undeclared identifier
+// --> gcd.pl0:8:3
+//  |
+//8 |   f := x;
+//  |   ^
+pub fn render(d: &Diagnostic, source: &str) -> String {
+    let mut out = format!("{}\n", headline(d));
+    let span = match d.span {
+        Some(span) => span,
+        None => {
+            out.push_str(&format!(" --> {}\n", location(d)));
+            return out;
+        }
+    };
+    let num = span.line.to_string();
+    let pad = " ".repeat(num.len());
+    out.push_str(&format!("{pad}--> {}\n", location(d)));
+
+    let text = match source.lines().nth((span.line - 1) as usize) {
+        Some(text) => text,
+        None => return out,
+    };
+    //Keep tabs in the underline so that carets line up with the source line
+    let lead: String = text
+        .chars()
+        .take((span.col - 1) as usize)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source
+        .get(span.start..span.end)
+        .map_or(0, |lexeme| lexeme.chars().count())
+        .max(1);
+    out.push_str(&format!("{pad} |\n"));
+    out.push_str(&format!("{num} | {text}\n"));
+    out.push_str(&format!("{pad} | {lead}{}\n", "^".repeat(width)));
+    out
+}
+
+//Diagnostic as one line JSON object, for editors and graders
+pub fn to_json(d: &Diagnostic) -> String {
+    let code = if d.code == 0 {
+        "null".to_owned()
+    } else {
+        d.code.to_string()
+    };
+    let (line, column, span) = match d.span {
+        Some(s) => (
+            s.line.to_string(),
+            s.col.to_string(),
+            format!("{{\"start\":{},\"end\":{}}}", s.start, s.end),
+        ),
+        None => ("null".to_owned(), "null".to_owned(), "null".to_owned()),
+    };
+    format!(
+        "{{\"code\":{code},\"message\":{},\"severity\":{},\"file\":{},\"line\":{line},\"column\":{column},\"span\":{span}}}",
+        quote(&d.message),
+        quote(&d.severity.to_string()),
+        quote_opt(d.file.as_deref()),
+    )
+}
@@ -1,6 +1,9 @@
-use crate::defs::{Fct, Instruction};
-use crate::parser::Parser;
+use crate::defs::{Fct, Instruction, Program};
+use crate::diagnostic::{line_span, Diagnostic};
+use crate::io::{Io, IoError};
+use std::fmt;
 use std::io;
+use std::time::{Duration, Instant};
 
 // lit 0,a : load constant a
 // opr 0,a : execute operation a
@@ -11,150 +14,545 @@ use std::io;
 // jmp 0,a : jump to a
 // jpc 0,a : jump conditional to a
 
-const STACK_SIZE: usize = 501;
+pub const STACK_SIZE: usize = 501;
+const TIME_CHECK_INTERVAL: u64 = 1024; //Instructions between clock checks
+
+//Limits for running untrusted programs, None is no limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterpreterConfig {
+    pub stack_size: usize,           //Stack slots, at least 4
+    pub max_steps: Option<u64>,      //Instructions executed ("fuel")
+    pub max_call_depth: Option<u32>, //Procedure calls active at the same time
+    pub timeout: Option<Duration>,   //Wall-clock time, not checked while waiting for input
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        InterpreterConfig {
+            stack_size: STACK_SIZE,
+            max_steps: None,
+            max_call_depth: None,
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    CallDepth(u32),
+    Time(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "step limit of {n} instructions"),
+            Limit::CallDepth(n) => write!(f, "call depth limit of {n}"),
+            Limit::Time(d) => write!(f, "time limit of {:.3} s", d.as_secs_f64()),
+        }
+    }
+}
+
+//Errors stopping the p-machine, first field is the address of the failing instruction
+#[derive(Debug)]
+pub enum RuntimeError {
+    DivisionByZero(i32),
+    StackOverflow(i32),
+    StackUnderflow(i32),
+    ArithmeticOverflow(i32),
+    BadJump(i32, i32),         //Jump, call or return to an address outside the code
+    BadAddress(i32, i32),      //Load, store or static link outside the stack
+    UnknownOpr(i32, i32),      //Opr with an unknown operation number
+    EndOfInput(i32),           //`?` with no input left
+    InvalidInput(i32, String), //`?` read something that is not an integer
+    Io(i32, io::Error),        //Reading or writing program input/output failed
+    LimitExceeded(i32, Limit),
+}
+
+impl RuntimeError {
+    //Address of the failing instruction
+    pub fn pc(&self) -> i32 {
+        match self {
+            RuntimeError::DivisionByZero(pc)
+            | RuntimeError::StackOverflow(pc)
+            | RuntimeError::StackUnderflow(pc)
+            | RuntimeError::ArithmeticOverflow(pc)
+            | RuntimeError::BadJump(pc, _)
+            | RuntimeError::BadAddress(pc, _)
+            | RuntimeError::UnknownOpr(pc, _)
+            | RuntimeError::EndOfInput(pc)
+            | RuntimeError::InvalidInput(pc, _)
+            | RuntimeError::Io(pc, _)
+            | RuntimeError::LimitExceeded(pc, _) => *pc,
+        }
+    }
+
+    //Source line of the failing instruction, if the program has a line table
+    pub fn line(&self, prog: &Program) -> Option<i32> {
+        prog.lines.get(self.pc() as usize).copied()
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::DivisionByZero(pc) => write!(f, "division by zero (pc {pc})"),
+            RuntimeError::StackOverflow(pc) => write!(f, "stack overflow (pc {pc})"),
+            RuntimeError::StackUnderflow(pc) => write!(f, "stack underflow (pc {pc})"),
+            RuntimeError::ArithmeticOverflow(pc) => write!(f, "arithmetic overflow (pc {pc})"),
+            RuntimeError::BadJump(pc, adr) => {
+                write!(f, "jump to {adr} outside the code (pc {pc})")
+            }
+            RuntimeError::BadAddress(pc, adr) => {
+                write!(f, "address {adr} outside the stack (pc {pc})")
+            }
+            RuntimeError::UnknownOpr(pc, n) => write!(f, "unknown operation opr {n} (pc {pc})"),
+            RuntimeError::EndOfInput(pc) => write!(f, "read past end of input (pc {pc})"),
+            RuntimeError::InvalidInput(pc, text) => {
+                write!(f, "invalid input {text:?}, integer expected (pc {pc})")
+            }
+            RuntimeError::Io(pc, e) => write!(f, "I/O error: {e} (pc {pc})"),
+            RuntimeError::LimitExceeded(pc, limit) => write!(f, "{limit} exceeded (pc {pc})"),
+        }
+    }
+}
+
+//Runtime error as diagnostic, pointing to the source line of the failing instruction
+pub fn runtime_diagnostic(e: &RuntimeError, prog: &Program, source: &str) -> Diagnostic {
+    let span = e.line(prog).map(|line| line_span(source, line));
+    Diagnostic::new(prog.name.as_deref(), span, 0, &e.to_string())
+}
+
+//Stack index, checked
+fn index(pc: i32, s: &[i32], adr: i32) -> Result<usize, RuntimeError> {
+    if adr < 0 || adr as usize >= s.len() {
+        return Err(RuntimeError::BadAddress(pc, adr));
+    }
+    Ok(adr as usize)
+}
+
+//Stack index of offset adr in the frame at base, checked: adr can be anything in
+//assembled or loaded code
+fn address(pc: i32, s: &[i32], base: i32, adr: i32) -> Result<usize, RuntimeError> {
+    match base.checked_add(adr) {
+        Some(a) => index(pc, s, a),
+        None => Err(RuntimeError::BadAddress(pc, adr)),
+    }
+}
 
 //Find base l levels down
-fn base(mut l: i32, b: i32, s: &[i32; STACK_SIZE]) -> i32 {
+pub fn base(pc: i32, mut l: i32, b: i32, s: &[i32]) -> Result<i32, RuntimeError> {
     let mut bl: i32;
     bl = b;
     while l > 0 {
-        bl = s[bl as usize];
-        l = l - 1;
-    }
-    bl
-}
-
-fn read_i32() -> i32 {
-    let mut input = String::new();
-    let read_res = io::stdin().read_line(&mut input);
-    if read_res.is_err() {
-        return 0;
-    }
-    let ret = input.trim().parse::<i32>();
-    if ret.is_err() {
-        0
-    } else {
-        ret.unwrap()
-    }
-}
-
-pub fn interpret(par: Parser) {
-    let mut p: i32; //Program register
-    let mut b: i32; //Baseregister
-    let mut t: usize; //Topstack register
-    let mut i: Instruction;
-    let mut s: [i32; STACK_SIZE] = [0; STACK_SIZE];
-
-    println!(" start pl/0");
-    t = 0;
-    b = 1;
-    p = 0;
-    s[1] = 0;
-    s[2] = 0;
-    s[3] = 0;
-    loop {
-        i = par.code[p as usize];
-        p += 1;
-        match i.fct {
-            Fct::Lit => {
-                t += 1;
-                s[t] = i.adr;
-            }
-            Fct::Opr => match i.adr {
-                0 => {
-                    //return
-                    t = (b - 1) as usize;
-                    p = s[t + 3];
-                    b = s[t + 2]
-                }
-                1 => {
-                    s[t] = -s[t];
-                }
-                2 => {
-                    t = t - 1;
-                    s[t] = s[t] + s[t + 1];
-                }
-                3 => {
-                    t = t - 1;
-                    s[t] = s[t] - s[t + 1];
-                }
-                4 => {
-                    t = t - 1;
-                    s[t] = s[t] * s[t + 1];
-                }
-                5 => {
-                    t = t - 1;
-                    s[t] = s[t] / s[t + 1];
-                }
-                6 => {
-                    s[t] = s[t] % 2;
-                }
-                8 => {
-                    t = t - 1;
-                    s[t] = if s[t] == s[t + 1] { 1 } else { 0 };
-                }
-                9 => {
-                    t = t - 1;
-                    s[t] = if s[t] != s[t + 1] { 1 } else { 0 };
-                }
-                10 => {
-                    t = t - 1;
-                    s[t] = if s[t] < s[t + 1] { 1 } else { 0 };
-                }
-                11 => {
-                    t = t - 1;
-                    s[t] = if s[t] >= s[t + 1] { 1 } else { 0 };
-                }
-                12 => {
-                    t = t - 1;
-                    s[t] = if s[t] > s[t + 1] { 1 } else { 0 };
-                }
-                13 => {
-                    t = t - 1;
-                    s[t] = if s[t] <= s[t + 1] { 1 } else { 0 };
+        bl = s[index(pc, s, bl)?];
+        l -= 1;
+    }
+    Ok(bl)
+}
+
+fn push(pc: i32, s: &[i32], t: &mut usize) -> Result<(), RuntimeError> {
+    if *t + 1 >= s.len() {
+        return Err(RuntimeError::StackOverflow(pc));
+    }
+    *t += 1;
+    Ok(())
+}
+
+//At least n values must be on the stack, slot 0 is never a value
+fn need(pc: i32, t: usize, n: usize) -> Result<(), RuntimeError> {
+    if t < n {
+        return Err(RuntimeError::StackUnderflow(pc));
+    }
+    Ok(())
+}
+
+//Drop top of stack, n values are needed by the operation
+fn pop(pc: i32, t: &mut usize, n: usize) -> Result<(), RuntimeError> {
+    need(pc, *t, n)?;
+    *t -= 1;
+    Ok(())
+}
+
+fn jump(pc: i32, prog: &Program, adr: i32) -> Result<i32, RuntimeError> {
+    if adr < 0 || adr as usize >= prog.code.len() {
+        return Err(RuntimeError::BadJump(pc, adr));
+    }
+    Ok(adr)
+}
+
+//Binary operation on two topmost values
+fn arith(
+    pc: i32,
+    s: &mut [i32],
+    t: &mut usize,
+    f: fn(i32, i32) -> Option<i32>,
+) -> Result<(), RuntimeError> {
+    pop(pc, t, 2)?;
+    s[*t] = f(s[*t], s[*t + 1]).ok_or(RuntimeError::ArithmeticOverflow(pc))?;
+    Ok(())
+}
+
+fn compare(
+    pc: i32,
+    s: &mut [i32],
+    t: &mut usize,
+    f: fn(&i32, &i32) -> bool,
+) -> Result<(), RuntimeError> {
+    pop(pc, t, 2)?;
+    s[*t] = if f(&s[*t], &s[*t + 1]) { 1 } else { 0 };
+    Ok(())
+}
+
+fn io_error(pc: i32, e: IoError) -> RuntimeError {
+    match e {
+        IoError::EndOfInput => RuntimeError::EndOfInput(pc),
+        IoError::InvalidInput(text) => RuntimeError::InvalidInput(pc, text),
+        IoError::Io(e) => RuntimeError::Io(pc, e),
+    }
+}
+
+//State of the p-machine, so that it can be run one instruction at a time
+pub struct Machine<'a> {
+    pub prog: &'a Program,
+    pub config: InterpreterConfig,
+    pub p: i32,       //Program register
+    pub b: i32,       //Baseregister
+    pub t: usize,     //Topstack register
+    pub s: Vec<i32>,  //Stack, slot 0 is unused
+    pub steps: u64,   //Instructions executed
+    pub depth: u32,   //Active procedure calls
+    pub halted: bool, //Main program has returned
+    start: Instant,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(prog: &'a Program, config: &InterpreterConfig) -> Result<Self, RuntimeError> {
+        let p = jump(prog.entry, prog, prog.entry)?;
+        let mut s: Vec<i32> = vec![0; config.stack_size];
+        if s.len() < 4 {
+            return Err(RuntimeError::StackOverflow(p));
+        }
+        s[1] = 0;
+        s[2] = 0;
+        s[3] = 0;
+        Ok(Machine {
+            prog,
+            config: *config,
+            p,
+            b: 1,
+            t: 0,
+            s,
+            steps: 0,
+            depth: 0,
+            halted: false,
+            start: Instant::now(),
+        })
+    }
+}
+
+//Stop if a limit is reached before executing the instruction at pc
+fn check_limits(m: &Machine, pc: i32) -> Result<(), RuntimeError> {
+    if let Some(max) = m.config.max_steps {
+        if m.steps >= max {
+            return Err(RuntimeError::LimitExceeded(pc, Limit::Steps(max)));
+        }
+    }
+    if let Some(max) = m.config.max_call_depth {
+        if m.depth > max {
+            return Err(RuntimeError::LimitExceeded(pc, Limit::CallDepth(max)));
+        }
+    }
+    if let Some(max) = m.config.timeout {
+        if m.steps.is_multiple_of(TIME_CHECK_INTERVAL) && m.start.elapsed() > max {
+            return Err(RuntimeError::LimitExceeded(pc, Limit::Time(max)));
+        }
+    }
+    Ok(())
+}
+
+//Execute one instruction, sets halted when the main program returns
+pub fn step(m: &mut Machine, io: &mut dyn Io) -> Result<(), RuntimeError> {
+    let prog = m.prog;
+    let pc: i32 = m.p; //Address of current instruction
+    check_limits(m, pc)?;
+    m.steps += 1;
+    let i: Instruction = prog.code[pc as usize];
+    let s = &mut m.s;
+    let mut p = pc + 1;
+    let mut b = m.b;
+    let mut t = m.t;
+    match i.fct {
+        Fct::Lit => {
+            push(pc, s, &mut t)?;
+            s[t] = i.adr;
+        }
+        Fct::Opr => match i.adr {
+            0 => {
+                //return
+                let frame = index(pc, s, b)?;
+                if frame == 0 || frame + 2 >= s.len() {
+                    return Err(RuntimeError::BadAddress(pc, b));
                 }
-                14 => {
-                    t = t + 1;
-                    s[t] = read_i32();
+                t = frame - 1;
+                p = s[t + 3];
+                b = s[t + 2];
+                m.depth = m.depth.saturating_sub(1);
+                if p != 0 {
+                    p = jump(pc, prog, p)?;
                 }
-                15 => {
-                    println!("{}", s[t]);
+            }
+            1 => {
+                need(pc, t, 1)?;
+                s[t] = s[t]
+                    .checked_neg()
+                    .ok_or(RuntimeError::ArithmeticOverflow(pc))?;
+            }
+            2 => arith(pc, s, &mut t, i32::checked_add)?,
+            3 => arith(pc, s, &mut t, i32::checked_sub)?,
+            4 => arith(pc, s, &mut t, i32::checked_mul)?,
+            5 => {
+                need(pc, t, 2)?;
+                if s[t] == 0 {
+                    return Err(RuntimeError::DivisionByZero(pc));
                 }
-                _ => {}
-            },
-            Fct::Lod => {
-                t += 1;
-                let ind = base(i.level, b, &s) + i.adr;
-                s[t] = s[ind as usize];
+                arith(pc, s, &mut t, i32::checked_div)?
             }
-            Fct::Sto => {
-                let ind = base(i.level, b, &s) + i.adr;
-                s[ind as usize] = s[t];
-                t = t - 1;
+            6 => {
+                need(pc, t, 1)?;
+                s[t] %= 2;
             }
-            Fct::Cal => {
-                s[t + 1] = base(i.level, b, &s);
-                s[t + 2] = b;
-                s[t + 3] = p;
-                b = (t + 1) as i32;
-                p = i.adr;
+            8 => compare(pc, s, &mut t, i32::eq)?,
+            9 => compare(pc, s, &mut t, i32::ne)?,
+            10 => compare(pc, s, &mut t, i32::lt)?,
+            11 => compare(pc, s, &mut t, i32::ge)?,
+            12 => compare(pc, s, &mut t, i32::gt)?,
+            13 => compare(pc, s, &mut t, i32::le)?,
+            14 => {
+                let n = io.read_int().map_err(|e| io_error(pc, e))?;
+                push(pc, s, &mut t)?;
+                s[t] = n;
             }
-            Fct::Int => {
-                t = t + i.adr as usize;
+            15 => {
+                need(pc, t, 1)?;
+                io.write_int(s[t]).map_err(|e| io_error(pc, e))?;
             }
-            Fct::Jmp => {
-                p = i.adr;
+            n => return Err(RuntimeError::UnknownOpr(pc, n)),
+        },
+        Fct::Lod => {
+            let ind = address(pc, s, base(pc, i.level, b, s)?, i.adr)?;
+            push(pc, s, &mut t)?;
+            s[t] = s[ind];
+        }
+        Fct::Sto => {
+            let ind = address(pc, s, base(pc, i.level, b, s)?, i.adr)?;
+            pop(pc, &mut t, 1)?;
+            s[ind] = s[t + 1];
+        }
+        Fct::Cal => {
+            if t + 3 >= s.len() {
+                return Err(RuntimeError::StackOverflow(pc));
             }
-            Fct::Jpc => {
-                if s[t] == 0 {
-                    p = i.adr
-                }
-                t = t - 1;
+            m.depth += 1;
+            s[t + 1] = base(pc, i.level, b, s)?;
+            s[t + 2] = b;
+            s[t + 3] = p;
+            b = (t + 1) as i32;
+            p = jump(pc, prog, i.adr)?;
+        }
+        Fct::Int => {
+            let top = t as i64 + i.adr as i64;
+            if top < 0 {
+                return Err(RuntimeError::StackUnderflow(pc));
+            }
+            if top >= s.len() as i64 {
+                return Err(RuntimeError::StackOverflow(pc));
             }
+            t = top as usize;
         }
-        if p == 0 {
-            break;
+        Fct::Jmp => {
+            p = jump(pc, prog, i.adr)?;
+        }
+        Fct::Jpc => {
+            pop(pc, &mut t, 1)?;
+            if s[t + 1] == 0 {
+                p = jump(pc, prog, i.adr)?;
+            }
         }
     }
-    println!(" end pl/0");
+    if p != 0 && p as usize >= prog.code.len() {
+        return Err(RuntimeError::BadJump(pc, p));
+    }
+    m.p = p;
+    m.b = b;
+    m.t = t;
+    m.halted = p == 0;
+    Ok(())
+}
+
+pub fn interpret(
+    prog: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+) -> Result<(), RuntimeError> {
+    let mut m = Machine::new(prog, config)?;
+    while !m.halted {
+        step(&mut m, io)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{StreamIo, VecIo};
+
+    fn compile(src: &str) -> Program {
+        crate::compile(src).unwrap()
+    }
+
+    //Error from running `src` with `input`, after checking the output written before it
+    fn fails(src: &str, input: &[i32], output: &[i32]) -> (Program, RuntimeError) {
+        let prog = compile(src);
+        let mut io = VecIo::new(input);
+        let e = crate::run(&prog, &mut io).unwrap_err();
+        assert_eq!(io.output, output);
+        (prog, e)
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let src = "var x;\nbegin\n  ? x;\n  ! 10 / x;\n  ! 1\nend.\n";
+        let (prog, e) = fails(src, &[0], &[]);
+        assert!(matches!(e, RuntimeError::DivisionByZero(_)));
+        let i = prog.code[e.pc() as usize];
+        assert_eq!((i.fct, i.adr), (Fct::Opr, 5));
+        assert_eq!(e.line(&prog), Some(4));
+        assert_eq!(e.to_string(), format!("division by zero (pc {})", e.pc()));
+    }
+
+    #[test]
+    fn arithmetic_overflow() {
+        let cases = [
+            ("! x + 1", 2147483647, 2),
+            ("! x - 2", -2147483647, 3),
+            ("! x * 2", 1073741824, 4),
+            ("! -x", -2147483647 - 1, 1),
+            ("! x / (0 - 1)", -2147483647 - 1, 5),
+        ];
+        for (stmt, x, opr) in cases {
+            let src = format!("var x;\nbegin\n  ? x;\n  ! 0;\n  {stmt}\nend.\n");
+            let (prog, e) = fails(&src, &[x], &[0]);
+            assert!(
+                matches!(e, RuntimeError::ArithmeticOverflow(_)),
+                "{stmt}: {e}"
+            );
+            let i = prog.code[e.pc() as usize];
+            assert_eq!((i.fct, i.adr), (Fct::Opr, opr), "{stmt}");
+            assert_eq!(e.line(&prog), Some(5), "{stmt}");
+        }
+    }
+
+    #[test]
+    fn end_of_input() {
+        let src = "var x;\nbegin\n  ? x;\n  ! x;\n  ? x\nend.\n";
+        let (prog, e) = fails(src, &[7], &[7]);
+        assert!(matches!(e, RuntimeError::EndOfInput(_)));
+        let i = prog.code[e.pc() as usize];
+        assert_eq!((i.fct, i.adr), (Fct::Opr, 14));
+        assert_eq!(e.line(&prog), Some(5));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let prog = compile("var x;\nbegin\n  ? x;\n  ! x;\n  ? x\nend.\n");
+        let mut io = StreamIo::new(&b"12\n1x\n"[..], Vec::new());
+        let e = crate::run(&prog, &mut io).unwrap_err();
+        assert_eq!(io.output, b"12\n");
+        match &e {
+            RuntimeError::InvalidInput(_, text) => assert_eq!(text, "1x"),
+            e => panic!("{e}"),
+        }
+        assert_eq!(e.line(&prog), Some(5));
+        assert_eq!(
+            e.to_string(),
+            format!("invalid input \"1x\", integer expected (pc {})", e.pc())
+        );
+    }
+
+    fn limited(src: &str, config: InterpreterConfig) -> (Program, RuntimeError) {
+        let prog = compile(src);
+        let mut io = VecIo::new(&[]);
+        (
+            prog.clone(),
+            crate::run_with(&prog, &config, &mut io).unwrap_err(),
+        )
+    }
+
+    #[test]
+    fn step_limit() {
+        let src = "var x;\nbegin\n  x := 0;\n  while 0 = 0 do x := x + 1\nend.\n";
+        let config = InterpreterConfig {
+            max_steps: Some(100),
+            ..InterpreterConfig::default()
+        };
+        let (prog, e) = limited(src, config);
+        assert!(matches!(
+            e,
+            RuntimeError::LimitExceeded(_, Limit::Steps(100))
+        ));
+        assert_eq!(e.line(&prog), Some(4));
+        assert_eq!(
+            e.to_string(),
+            format!("step limit of 100 instructions exceeded (pc {})", e.pc())
+        );
+
+        let mut io = VecIo::new(&[]);
+        let prog = compile("begin ! 1; ! 2 end.");
+        crate::run_with(&prog, &config, &mut io).unwrap();
+        assert_eq!(io.output, [1, 2]);
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let src = "var n;\nprocedure p;\nbegin\n  if n > 0 then begin n := n - 1; call p end\nend;\nbegin\n  n := NUM;\n  call p\nend.\n";
+        let config = InterpreterConfig {
+            max_call_depth: Some(5),
+            ..InterpreterConfig::default()
+        };
+        let mut io = VecIo::new(&[]);
+        crate::run_with(&compile(&src.replace("NUM", "4")), &config, &mut io).unwrap();
+
+        let (prog, e) = limited(&src.replace("NUM", "5"), config);
+        assert!(matches!(
+            e,
+            RuntimeError::LimitExceeded(_, Limit::CallDepth(5))
+        ));
+        assert_eq!(prog.code[e.pc() as usize].fct, Fct::Int);
+        assert_eq!(
+            e.to_string(),
+            format!("call depth limit of 5 exceeded (pc {})", e.pc())
+        );
+    }
+
+    #[test]
+    fn stack_size() {
+        let src = "procedure p;\nbegin\n  call p\nend;\ncall p.\n";
+        let config = InterpreterConfig {
+            stack_size: 20,
+            ..InterpreterConfig::default()
+        };
+        let (prog, e) = limited(src, config);
+        assert!(matches!(e, RuntimeError::StackOverflow(_)));
+        assert_eq!(prog.code[e.pc() as usize].fct, Fct::Cal);
+        assert_eq!(e.line(&prog), Some(3));
+
+        let tiny = InterpreterConfig {
+            stack_size: 3,
+            ..InterpreterConfig::default()
+        };
+        let (_, e) = limited("begin end.", tiny);
+        assert!(matches!(e, RuntimeError::StackOverflow(_)));
+    }
 }
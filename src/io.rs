@@ -0,0 +1,222 @@
+//Input and output of a running PL/0 program. `?` calls read_int and `!` calls write_int,
+//so the interpreter does not care whether numbers come from a terminal, a test or a script.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, StdinLock, Stdout, Write};
+
+#[derive(Debug)]
+pub enum IoError {
+    EndOfInput,           //`?` with no input left
+    InvalidInput(String), //Input line that is not an integer
+    Io(io::Error),        //Underlying reader or writer failed
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoError::EndOfInput => write!(f, "end of input"),
+            IoError::InvalidInput(text) => write!(f, "invalid input {text:?}, integer expected"),
+            IoError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        IoError::Io(e)
+    }
+}
+
+pub trait Io {
+    fn read_int(&mut self) -> Result<i32, IoError>;
+    fn write_int(&mut self, n: i32) -> Result<(), IoError>;
+}
+
+//One integer per line from a reader, one per line to a writer
+pub struct StreamIo<R: BufRead, W: Write> {
+    pub input: R,
+    pub output: W,
+}
+
+impl<R: BufRead, W: Write> StreamIo<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        StreamIo { input, output }
+    }
+}
+
+//Standard input and output, as used by the command line
+pub fn stdio() -> StreamIo<StdinLock<'static>, Stdout> {
+    StreamIo::new(io::stdin().lock(), io::stdout())
+}
+
+impl<R: BufRead, W: Write> Io for StreamIo<R, W> {
+    fn read_int(&mut self) -> Result<i32, IoError> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(IoError::EndOfInput);
+        }
+        let text = line.trim();
+        text.parse::<i32>()
+            .map_err(|_| IoError::InvalidInput(text.to_owned()))
+    }
+
+    fn write_int(&mut self, n: i32) -> Result<(), IoError> {
+        writeln!(self.output, "{n}")?;
+        Ok(())
+    }
+}
+
+//Inputs taken from a vector, outputs collected into another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VecIo {
+    pub input: VecDeque<i32>,
+    pub output: Vec<i32>,
+}
+
+impl VecIo {
+    pub fn new(input: &[i32]) -> Self {
+        VecIo {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Io for VecIo {
+    fn read_int(&mut self) -> Result<i32, IoError> {
+        self.input.pop_front().ok_or(IoError::EndOfInput)
+    }
+
+    fn write_int(&mut self, n: i32) -> Result<(), IoError> {
+        self.output.push(n);
+        Ok(())
+    }
+}
+
+//One step of an expected conversation with the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Read(i32),  //Program reads, gets this number
+    Write(i32), //Program must write this number
+}
+
+//Replays a script of reads and writes, any other order or value is an error
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptedIo {
+    pub script: VecDeque<Event>,
+}
+
+impl ScriptedIo {
+    pub fn new(script: &[Event]) -> Self {
+        ScriptedIo {
+            script: script.iter().copied().collect(),
+        }
+    }
+
+    //True when the program went through the whole script
+    pub fn done(&self) -> bool {
+        self.script.is_empty()
+    }
+}
+
+fn script_error(msg: String) -> IoError {
+    IoError::Io(io::Error::other(msg))
+}
+
+impl Io for ScriptedIo {
+    fn read_int(&mut self) -> Result<i32, IoError> {
+        match self.script.pop_front() {
+            Some(Event::Read(n)) => Ok(n),
+            Some(Event::Write(n)) => Err(script_error(format!("read, expected write of {n}"))),
+            None => Err(IoError::EndOfInput),
+        }
+    }
+
+    fn write_int(&mut self, n: i32) -> Result<(), IoError> {
+        match self.script.pop_front() {
+            Some(Event::Write(m)) if m == n => Ok(()),
+            Some(Event::Write(m)) => Err(script_error(format!("wrote {n}, expected {m}"))),
+            Some(Event::Read(_)) => Err(script_error(format!("wrote {n}, expected read"))),
+            None => Err(script_error(format!("wrote {n} after end of script"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuntimeError;
+
+    //Reads numbers until 0 and writes each one doubled
+    const DOUBLE: &str =
+        "var x;\nbegin\n  ? x;\n  while x <> 0 do\n  begin\n    ! 2 * x;\n    ? x\n  end\nend.\n";
+
+    fn run(io: &mut dyn Io) -> Result<(), RuntimeError> {
+        crate::run(&crate::compile(DOUBLE).unwrap(), io)
+    }
+
+    #[test]
+    fn scripted_conversation() {
+        use Event::{Read, Write};
+        let mut io = ScriptedIo::new(&[Read(3), Write(6), Read(-4), Write(-8), Read(0)]);
+        run(&mut io).unwrap();
+        assert!(io.done());
+    }
+
+    #[test]
+    fn scripted_mismatch() {
+        use Event::{Read, Write};
+        let mut io = ScriptedIo::new(&[Read(3), Write(7)]);
+        match run(&mut io).unwrap_err() {
+            RuntimeError::Io(_, e) => assert_eq!(e.to_string(), "wrote 6, expected 7"),
+            e => panic!("{e}"),
+        }
+        let mut io = ScriptedIo::new(&[Read(3), Read(4)]);
+        match run(&mut io).unwrap_err() {
+            RuntimeError::Io(_, e) => assert_eq!(e.to_string(), "wrote 6, expected read"),
+            e => panic!("{e}"),
+        }
+        let mut io = ScriptedIo::new(&[Write(1)]);
+        match run(&mut io).unwrap_err() {
+            RuntimeError::Io(_, e) => assert_eq!(e.to_string(), "read, expected write of 1"),
+            e => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn scripted_input_exhausted() {
+        use Event::{Read, Write};
+        let mut io = ScriptedIo::new(&[Read(5), Write(10)]);
+        assert!(matches!(run(&mut io), Err(RuntimeError::EndOfInput(_))));
+        assert!(io.done());
+    }
+
+    #[test]
+    fn vec_io() {
+        let mut io = VecIo::new(&[1, 2, 0]);
+        run(&mut io).unwrap();
+        assert_eq!(io.output, [2, 4]);
+        let mut io = VecIo::new(&[1, 2]);
+        assert!(matches!(run(&mut io), Err(RuntimeError::EndOfInput(_))));
+        assert_eq!(io.output, [2, 4]);
+    }
+
+    #[test]
+    fn stream_io() {
+        let mut io = StreamIo::new(&b" 21 \n-1\n0\n"[..], Vec::new());
+        run(&mut io).unwrap();
+        assert_eq!(io.output, b"42\n-2\n");
+        let mut io = StreamIo::new(&b"21\n"[..], Vec::new());
+        assert!(matches!(run(&mut io), Err(RuntimeError::EndOfInput(_))));
+        for bad in ["x", "", "1.5", "99999999999"] {
+            let input = format!("1\n{bad}\n");
+            let mut io = StreamIo::new(input.as_bytes(), Vec::new());
+            match run(&mut io).unwrap_err() {
+                RuntimeError::InvalidInput(_, text) => assert_eq!(text, bad),
+                e => panic!("{bad:?}: {e}"),
+            }
+            assert_eq!(io.output, b"2\n");
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//Minimal JSON output helpers, just enough for diagnostics and listings
+
+//String as a quoted and escaped JSON string
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+//Optional string, None is null
+pub fn quote_opt(s: Option<&str>) -> String {
+    match s {
+        Some(s) => quote(s),
+        None => "null".to_owned(),
+    }
+}
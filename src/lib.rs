@@ -0,0 +1,105 @@
+//! PL/0 compiler and p-code interpreter as a library.
+//!
+//! `compile` turns PL/0 source into a `Program`, `run` executes it.
+//! Neither exits the process: errors are returned to the caller.
+
+pub mod asm;
+pub mod ast;
+pub mod codegen;
+pub mod coverage;
+pub mod debugger;
+pub mod defs;
+pub mod diagnostic;
+pub mod interpreter;
+pub mod io;
+pub mod json;
+pub mod listing;
+pub mod object;
+pub mod parser;
+pub mod pretty;
+pub mod profile;
+pub mod scanner;
+pub mod source;
+pub mod token;
+pub mod trace;
+
+pub use crate::defs::Program;
+pub use crate::diagnostic::Diagnostic;
+pub use crate::interpreter::{InterpreterConfig, RuntimeError};
+pub use crate::io::{Io, ScriptedIo, StreamIo, VecIo};
+pub use crate::source::Source;
+
+use crate::codegen::{codegen_run, program, CodeGen};
+use crate::interpreter::interpret;
+use crate::parser::{parser_run, Parser};
+use crate::profile::{interpret_profiled, Profile};
+use crate::trace::{interpret_traced, Tracer};
+
+/// Compile PL/0 source text into p-code.
+pub fn compile(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    compile_source(&Source::new(source))
+}
+
+/// Parse a named source into its syntax tree without generating code.
+pub fn parse_source(src: &Source) -> Result<ast::Program, Vec<Diagnostic>> {
+    let mut parser = Parser::new(&src.text, src.name.as_deref(), src.dialect);
+    let tree = parser_run(&mut parser);
+    if parser.diags.is_empty() {
+        Ok(tree)
+    } else {
+        Err(parser.diags)
+    }
+}
+
+/// Compile a named source, the name is reported in diagnostics.
+pub fn compile_source(src: &Source) -> Result<Program, Vec<Diagnostic>> {
+    let mut parser = Parser::new(&src.text, src.name.as_deref(), src.dialect);
+    let tree = parser_run(&mut parser);
+    let mut gen = CodeGen::new(src.name.as_deref());
+    codegen_run(&mut gen, &tree);
+
+    //Syntax errors first, then the first error at every position
+    let mut diags = parser.diags;
+    diags.append(&mut gen.diags);
+    diags.sort_by_key(|d| d.span.map_or(0, |s| s.start));
+    diags.dedup_by_key(|d| d.span.map(|s| s.start));
+    if diags.is_empty() {
+        Ok(program(&gen))
+    } else {
+        Err(diags)
+    }
+}
+
+/// Run a compiled program, `?` reads from `io` and `!` writes to it.
+pub fn run(program: &Program, io: &mut dyn Io) -> Result<(), RuntimeError> {
+    interpret(program, &InterpreterConfig::default(), io)
+}
+
+/// Run a compiled program within the limits of `config`.
+pub fn run_with(
+    program: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+) -> Result<(), RuntimeError> {
+    interpret(program, config, io)
+}
+
+/// Run a compiled program and write a trace line for every instruction it executes.
+pub fn run_traced(
+    program: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+    tracer: &mut Tracer,
+) -> Result<(), RuntimeError> {
+    interpret_traced(program, config, io, tracer)
+}
+
+/// Run a compiled program counting how many times each instruction is executed.
+pub fn run_profiled(
+    program: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+    profile: &mut Profile,
+) -> Result<(), RuntimeError> {
+    interpret_profiled(program, config, io, profile)
+}
@@ -0,0 +1,81 @@
+use crate::defs::{fct_as_i32, Instruction, Program, MNEMONICS};
+
+//One instruction in the traditional format: address, mnemonic, level, address field
+pub fn instr_listing(adr: usize, instr: &Instruction) -> String {
+    let ind = fct_as_i32(instr.fct);
+    format!(
+        "{adr:>5}{:>5}{:>3}{:>5}",
+        MNEMONICS[ind as usize], instr.level, instr.adr
+    )
+}
+
+//Address of the first instruction generated for a line or after it. Lines are not in
+//address order: a loop's closing jmp belongs to its while line.
+fn first_adr(prog: &Program, line: i32) -> usize {
+    prog.lines
+        .iter()
+        .position(|l| *l >= line)
+        .unwrap_or(prog.lines.len())
+}
+
+//Source listing, every line prefixed with the address of the first instruction generated for it
+pub fn source_listing(source: &str, prog: &Program) -> String {
+    let mut out = String::new();
+    for (i, text) in source.lines().enumerate() {
+        let line = i as i32 + 1;
+        let cx = first_adr(prog, line);
+        out.push_str(&format!("{cx:5} {text}\n"));
+    }
+    out
+}
+
+//Code listing, one instruction per line
+pub fn code_listing(prog: &Program) -> String {
+    let mut out = String::new();
+    for (i, instr) in prog.code.iter().enumerate() {
+        out.push_str(&format!("{}\n", instr_listing(i, instr)));
+    }
+    out
+}
+
+//Source lines each followed by the code generated for it
+pub fn annotated_listing(source: &str, prog: &Program) -> String {
+    let mut out = String::new();
+    for (i, text) in source.lines().enumerate() {
+        let line = i as i32 + 1;
+        let cx = first_adr(prog, line);
+        out.push_str(&format!("{cx:5} {text}\n"));
+        for (adr, instr) in prog.code.iter().enumerate() {
+            if prog.lines.get(adr) == Some(&line) {
+                out.push_str(&format!("      {}\n", instr_listing(adr, instr)));
+            }
+        }
+    }
+    out
+}
+
+//Code and line table as a JSON object
+pub fn code_json(prog: &Program) -> String {
+    let instrs: Vec<String> = prog
+        .code
+        .iter()
+        .enumerate()
+        .map(|(adr, instr)| {
+            let line = match prog.lines.get(adr) {
+                Some(line) => line.to_string(),
+                None => "null".to_owned(),
+            };
+            format!(
+                "{{\"fct\":\"{}\",\"level\":{},\"adr\":{},\"line\":{line}}}",
+                MNEMONICS[fct_as_i32(instr.fct) as usize],
+                instr.level,
+                instr.adr
+            )
+        })
+        .collect();
+    format!(
+        "{{\"entry\":{},\"code\":[{}]}}\n",
+        prog.entry,
+        instrs.join(",")
+    )
+}
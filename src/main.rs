@@ -1,58 +1,514 @@
-use defs::*;
+use pl0r::asm::assemble;
+use pl0r::coverage::{coverage_lcov, coverage_listing};
+use pl0r::debugger::debug;
+use pl0r::defs::*;
+use pl0r::diagnostic::{render, to_json};
+use pl0r::interpreter::runtime_diagnostic;
+use pl0r::listing::{annotated_listing, code_json, code_listing, source_listing};
+use pl0r::object;
+use pl0r::pretty::format_program;
+use pl0r::profile::{report as profile_report, Profile};
+use pl0r::trace::{TraceFormat, Tracer};
+use pl0r::{Diagnostic, InterpreterConfig, Program, RuntimeError, Source};
 use std::env;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
 
-mod defs;
-mod interpreter;
-mod parser;
-mod scanner;
-mod token;
+const VER: &str = "0.1.0";
 
-use crate::interpreter::interpret;
-use crate::parser::{parser_run, Parser};
-use defs::ERR_MSGS;
+#[derive(PartialEq, Copy, Clone)]
+enum ErrorFormat {
+    Human, //rustc style with source snippet
+    Json,  //One JSON object per line
+}
 
-const VER: &str = "0.1.0";
+#[derive(PartialEq, Copy, Clone)]
+enum Target {
+    Pcode, //Listing format
+    Json,  //Code and line table as JSON
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum Command {
+    Run,          //Compile and interpret
+    Check,        //Compile only, errors and exit code
+    List,         //Source annotated with generated code
+    Disasm,       //P-code listing
+    Emit(Target), //Code in a format for other tools
+    Compile,      //Write object file
+    Exec,         //Run object file
+    Asm,          //Assemble p-code text and run it or write object file
+    Debug,        //Run under the interactive debugger
+    Fmt,          //Print the source in the canonical layout
+}
 
-static HAD_ERROR: AtomicBool = AtomicBool::new(false);
+//Where a listing goes: None is stderr, Some is a file
+type ListDest = Option<String>;
+
+struct Options {
+    command: Command,
+    error_format: ErrorFormat,
+    list_source: Option<ListDest>,
+    list_code: Option<ListDest>,
+    trace: Option<ListDest>,
+    trace_format: TraceFormat,
+    trace_stack: bool, //Trace shows the current frame, not only top of stack
+    profile: Option<ListDest>,
+    coverage: Option<ListDest>,
+    lcov: bool,             //Coverage in lcov format instead of annotated listing
+    quiet: bool,            //No banners
+    output: Option<String>, //Object file to write
+    text_object: bool,      //Object file in text format
+    strip: bool,            //Object file without debug info
+    check: bool,            //fmt: only tell if the source is formatted
+    dialect: Dialect,       //Language options for PL/0 source
+    limits: InterpreterConfig,
+    srcfile: String,
+}
 
 fn usage() {
-    println!("Usage: pl0r srcfile");
+    println!("Usage: pl0r [command] [options] srcfile");
+    println!("       srcfile - (a dash) reads the program from standard input");
+    println!("Commands:");
+    println!("  run                        Compile and run the program (default)");
+    println!("  check                      Only compile, exit code tells if there were errors");
+    println!("  list                       Source listing annotated with generated p-code");
+    println!("  disasm                     P-code listing");
+    println!("  emit --target=pcode|json   Generated code for other tools");
+    println!("  compile [-o FILE]          Write object file, default srcfile with .p0c extension");
+    println!("  exec                       Run an object file");
+    println!(
+        "  asm [-o FILE]              Assemble p-code listing and run it, or write object file"
+    );
+    println!("  debug                      Run the program in the interactive debugger");
+    println!("  fmt [--check]              Print the source formatted, --check fails if it is not");
+    println!("Options:");
+    println!("  --error-format=human|json  Format of error messages");
+    println!("  --list-source[=FILE]       run: source listing with addresses to stderr or FILE");
+    println!("  --list-code[=FILE]         run: p-code listing to stderr or FILE");
+    println!(
+        "  --trace[=FILE]             run, exec: trace executed instructions to stderr or FILE"
+    );
+    println!("  --trace-format=text|json   run, exec: trace as text or JSON lines");
+    println!("  --trace-stack              run, exec: trace the current frame, not only the top");
+    println!("  --profile[=FILE]           run, exec: instruction counts and hot spots to stderr or FILE");
+    println!(
+        "  --coverage[=FILE]          run, exec: executed lines and branches to stderr or FILE"
+    );
+    println!("  --coverage-format=listing|lcov  run, exec: annotated source or lcov tracefile");
+    println!("  --quiet                    run, exec: no banners, stdout has only program output");
+    println!("  --format=binary|text       compile: object file format");
+    println!("  --strip                    compile: leave out debug info");
+    println!("  --nested-comments          {{ }} and (* *) comments may contain other comments");
+    println!("  --classic-relops           Only [, ] and # for <=, >= and <> (Wirth 1976)");
+    println!("  --stack-size=N             run, exec, debug: stack size in words (default 501)");
+    println!("  --max-steps=N              run, exec, debug: stop after N instructions");
+    println!(
+        "  --max-depth=N              run, exec, debug: allow at most N nested procedure calls"
+    );
+    println!("  --timeout=SECS             run, exec, debug: stop after SECS seconds");
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let (mut command, args) = match args.first().map(|a| a.as_str()) {
+        Some("run") => (Command::Run, &args[1..]),
+        Some("check") => (Command::Check, &args[1..]),
+        Some("list") => (Command::List, &args[1..]),
+        Some("disasm") => (Command::Disasm, &args[1..]),
+        Some("emit") => (Command::Emit(Target::Pcode), &args[1..]),
+        Some("compile") => (Command::Compile, &args[1..]),
+        Some("exec") => (Command::Exec, &args[1..]),
+        Some("asm") => (Command::Asm, &args[1..]),
+        Some("debug") => (Command::Debug, &args[1..]),
+        Some("fmt") => (Command::Fmt, &args[1..]),
+        _ => (Command::Run, args),
+    };
+    let mut error_format = ErrorFormat::Human;
+    let mut list_source = None;
+    let mut list_code = None;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_stack = false;
+    let mut profile = None;
+    let mut coverage = None;
+    let mut lcov = false;
+    let mut quiet = false;
+    let mut output = None;
+    let mut text_object = false;
+    let mut strip = false;
+    let mut check = false;
+    let mut dialect = Dialect::default();
+    let mut limits = InterpreterConfig::default();
+    let mut has_limits = false;
+    let mut srcfile = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--error-format=human" => error_format = ErrorFormat::Human,
+            "--error-format=json" => error_format = ErrorFormat::Json,
+            "--list-source" => list_source = Some(None),
+            "--list-code" => list_code = Some(None),
+            "--trace" => trace = Some(None),
+            "--trace-format=text" => trace_format = TraceFormat::Text,
+            "--trace-format=json" => trace_format = TraceFormat::Json,
+            "--trace-stack" => trace_stack = true,
+            "--profile" => profile = Some(None),
+            "--coverage" => coverage = Some(None),
+            "--coverage-format=listing" => lcov = false,
+            "--coverage-format=lcov" => lcov = true,
+            "--quiet" => quiet = true,
+            "-o" => output = Some(args.next()?.to_owned()),
+            "--format=binary" => text_object = false,
+            "--format=text" => text_object = true,
+            "--strip" => strip = true,
+            "--check" => check = true,
+            "--nested-comments" => dialect.nested_comments = true,
+            "--classic-relops" => dialect.classic_relops = true,
+            a if a.starts_with("--list-source=") => {
+                list_source = Some(Some(a["--list-source=".len()..].to_owned()))
+            }
+            a if a.starts_with("--list-code=") => {
+                list_code = Some(Some(a["--list-code=".len()..].to_owned()))
+            }
+            a if a.starts_with("--trace=") => trace = Some(Some(a["--trace=".len()..].to_owned())),
+            a if a.starts_with("--profile=") => {
+                profile = Some(Some(a["--profile=".len()..].to_owned()))
+            }
+            a if a.starts_with("--coverage=") => {
+                coverage = Some(Some(a["--coverage=".len()..].to_owned()))
+            }
+            a if a.starts_with("--stack-size=") => {
+                limits.stack_size = a["--stack-size=".len()..].parse().ok()?;
+                has_limits = true;
+            }
+            a if a.starts_with("--max-steps=") => {
+                limits.max_steps = Some(a["--max-steps=".len()..].parse().ok()?);
+                has_limits = true;
+            }
+            a if a.starts_with("--max-depth=") => {
+                limits.max_call_depth = Some(a["--max-depth=".len()..].parse().ok()?);
+                has_limits = true;
+            }
+            a if a.starts_with("--timeout=") => {
+                let secs: f64 = a["--timeout=".len()..].parse().ok()?;
+                limits.timeout = Some(Duration::try_from_secs_f64(secs).ok()?);
+                has_limits = true;
+            }
+            a if a.starts_with("--target=") => {
+                let target = match &a["--target=".len()..] {
+                    "pcode" => Target::Pcode,
+                    "json" => Target::Json,
+                    _ => return None,
+                };
+                match command {
+                    Command::Emit(_) => command = Command::Emit(target),
+                    _ => return None,
+                }
+            }
+            a if a.starts_with("--") => return None,
+            a => {
+                if srcfile.is_some() {
+                    return None;
+                }
+                srcfile = Some(a.to_owned());
+            }
+        }
+    }
+    let runs = matches!(command, Command::Run | Command::Exec | Command::Asm);
+    let writes_object = matches!(command, Command::Compile | Command::Asm);
+    if command != Command::Run && list_source.is_some() {
+        return None;
+    }
+    let traces = trace.is_some() || trace_format != TraceFormat::Text || trace_stack;
+    let counts = profile.is_some() || coverage.is_some() || lcov;
+    if !runs && (list_code.is_some() || quiet || traces || counts) {
+        return None;
+    }
+    if trace.is_some() && counts {
+        return None;
+    }
+    if !runs && command != Command::Debug && has_limits {
+        return None;
+    }
+    if !writes_object && (output.is_some() || text_object || strip) {
+        return None;
+    }
+    if command != Command::Fmt && check {
+        return None;
+    }
+    if matches!(command, Command::Exec | Command::Asm) && dialect != Dialect::default() {
+        return None;
+    }
+    Some(Options {
+        command,
+        error_format,
+        list_source,
+        list_code,
+        trace,
+        trace_format,
+        trace_stack,
+        profile,
+        coverage,
+        lcov,
+        quiet,
+        output,
+        text_object,
+        strip,
+        check,
+        dialect,
+        limits,
+        srcfile: srcfile?,
+    })
 }
 
 fn main() {
-    println!("PL0R {VER}: PL/0 in Rust (c) Jari Korhonen, 2023");
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        usage();
-        std::process::exit(EX_USAGE);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let opts = match parse_args(&args) {
+        Some(opts) => opts,
+        None => {
+            println!("PL0R {VER}: PL/0 in Rust (c) Jari Korhonen, 2023");
+            usage();
+            std::process::exit(EX_USAGE);
+        }
+    };
+    if opts.command == Command::Exec {
+        exec(&opts);
+        return;
+    }
+    let source = load(&opts);
+    if opts.command == Command::Fmt {
+        format_source(&opts, &source);
+        return;
+    }
+    let program = match opts.command {
+        Command::Asm => assemble_source(&opts, &source),
+        _ => compile(&opts, &source),
+    };
+    match opts.command {
+        Command::Run => run(&opts, &source, &program),
+        Command::Compile => write_object(&opts, &program),
+        Command::Asm if opts.output.is_some() => write_object(&opts, &program),
+        Command::Asm => run(&opts, &source, &program),
+        Command::Debug => debug_program(&opts, &source, &program),
+        Command::Exec | Command::Fmt => {}
+        Command::Check => {}
+        Command::List => print!("{}", annotated_listing(&source.text, &program)),
+        Command::Disasm => print!("{}", code_listing(&program)),
+        Command::Emit(Target::Pcode) => print!("{}", code_listing(&program)),
+        Command::Emit(Target::Json) => print!("{}", code_json(&program)),
+    }
+}
+
+//Banners go to stderr so that stdout has only the output of the PL/0 program
+fn banner(opts: &Options, text: &str) {
+    if !opts.quiet {
+        eprintln!("{text}");
     }
-    run_file(&args[1]);
 }
 
-fn run_file(srcfile: &str) {
-    run(srcfile);
-    if true == HAD_ERROR.load(Ordering::Relaxed) {
-        std::process::exit(EX_DATAERR);
+//Write listings, both to the same file if so asked
+fn write_listings(listings: &[(&Option<ListDest>, String)]) {
+    let mut files: Vec<(&str, String)> = Vec::new();
+    for (dest, text) in listings {
+        match dest {
+            None => {}
+            Some(None) => eprint!("{text}"),
+            Some(Some(path)) => match files.iter_mut().find(|(p, _)| p == path) {
+                Some((_, all)) => all.push_str(text),
+                None => files.push((path, text.clone())),
+            },
+        }
+    }
+    for (path, text) in files {
+        if let Err(e) = fs::write(path, text) {
+            eprintln!("Could not write listing file {path}, error {e}");
+            std::process::exit(EX_IOERR);
+        }
+    }
+}
+
+fn load(opts: &Options) -> Source {
+    match Source::load(&opts.srcfile) {
+        Ok(s) => Source {
+            dialect: opts.dialect,
+            ..s
+        },
+        Err(e) => {
+            eprintln!("Could not read source file {}, error {e}", opts.srcfile);
+            std::process::exit(EX_NOINPUT);
+        }
+    }
+}
+
+fn compile(opts: &Options, source: &Source) -> Program {
+    match pl0r::compile_source(source) {
+        Ok(program) => program,
+        Err(diags) => {
+            for d in &diags {
+                report(opts, d, source);
+            }
+            std::process::exit(EX_DATAERR);
+        }
+    }
+}
+
+//Print the formatted source, or with --check report the first line that differs
+fn format_source(opts: &Options, source: &Source) {
+    let tree = match pl0r::parse_source(source) {
+        Ok(tree) => tree,
+        Err(diags) => {
+            for d in &diags {
+                report(opts, d, source);
+            }
+            std::process::exit(EX_DATAERR);
+        }
+    };
+    let text = format_program(&tree, &source.text, source.dialect);
+    if !opts.check {
+        print!("{text}");
+    } else if text != source.text {
+        let line = text
+            .lines()
+            .zip(source.text.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| text.lines().count().min(source.text.lines().count()));
+        eprintln!("{}:{}: not formatted", opts.srcfile, line + 1);
+        std::process::exit(1);
     }
 }
 
-fn run(src: &str) {
-    let mut parser = Parser::new(src);
-    parser = parser_run(parser);
-    interpret(parser)
+fn assemble_source(opts: &Options, source: &Source) -> Program {
+    match assemble(&source.text, source.name.as_deref()) {
+        Ok(program) => program,
+        Err(diags) => {
+            for d in &diags {
+                report(opts, d, source);
+            }
+            std::process::exit(EX_DATAERR);
+        }
+    }
 }
 
-fn report(line: i32, _where: &str, message: &str) {
-    eprintln!("[line {line} Error {_where} : {message}");
-    HAD_ERROR.store(true, Ordering::Relaxed)
+fn write_object(opts: &Options, program: &Program) {
+    let path = match &opts.output {
+        Some(path) => path.clone(),
+        None => {
+            let stem = opts.srcfile.strip_suffix(".pl0").unwrap_or(&opts.srcfile);
+            format!("{stem}.p0c")
+        }
+    };
+    if let Err(e) = object::save(&path, program, opts.text_object, !opts.strip) {
+        eprintln!("Could not write object file {path}, error {e}");
+        std::process::exit(EX_IOERR);
+    }
 }
 
-pub fn scan_error(line: i32, message: &str) {
-    report(line, "", message);
+fn exec(opts: &Options) {
+    let program = match object::load(&opts.srcfile) {
+        Ok(program) => program,
+        Err(object::ObjectError::Io(e)) => {
+            eprintln!("Could not read object file {}, error {e}", opts.srcfile);
+            std::process::exit(EX_NOINPUT);
+        }
+        Err(e) => {
+            eprintln!("Could not load object file {}, error {e}", opts.srcfile);
+            std::process::exit(EX_DATAERR);
+        }
+    };
+    let source = Source {
+        name: program.name.clone(),
+        text: String::new(),
+        dialect: Dialect::default(),
+    };
+    run(opts, &source, &program);
 }
 
-pub fn parse_error(line: i32, n: usize) {
-    report(line, "", ERR_MSGS[n]);
-    std::process::exit(EX_DATAERR);
+fn run(opts: &Options, source: &Source, program: &Program) {
+    banner(
+        opts,
+        &format!("PL0R {VER}: PL/0 in Rust (c) Jari Korhonen, 2023"),
+    );
+    write_listings(&[
+        (&opts.list_source, source_listing(&source.text, program)),
+        (&opts.list_code, code_listing(program)),
+    ]);
+
+    banner(opts, " start pl/0");
+    let counts = opts.profile.is_some() || opts.coverage.is_some();
+    let res = match &opts.trace {
+        None if counts => {
+            let mut profile = Profile::default();
+            let res =
+                pl0r::run_profiled(program, &opts.limits, &mut pl0r::io::stdio(), &mut profile);
+            let coverage = match opts.lcov {
+                true => coverage_lcov(program, &profile),
+                false => coverage_listing(&source.text, program, &profile),
+            };
+            write_listings(&[
+                (
+                    &opts.profile,
+                    profile_report(program, &source.text, &profile),
+                ),
+                (&opts.coverage, coverage),
+            ]);
+            res
+        }
+        None => pl0r::run_with(program, &opts.limits, &mut pl0r::io::stdio()),
+        Some(dest) => {
+            let mut out = trace_output(dest);
+            let mut tracer = Tracer {
+                out: &mut *out,
+                format: opts.trace_format,
+                stack: opts.trace_stack,
+            };
+            pl0r::run_traced(program, &opts.limits, &mut pl0r::io::stdio(), &mut tracer)
+        }
+    };
+    banner(opts, " end pl/0");
+    if let Err(e) = res {
+        report(opts, &runtime_diagnostic(&e, program, &source.text), source);
+        match e {
+            RuntimeError::Io(..) => std::process::exit(EX_IOERR),
+            RuntimeError::EndOfInput(_) | RuntimeError::InvalidInput(..) => {
+                std::process::exit(EX_DATAERR)
+            }
+            _ => std::process::exit(EX_SOFTWARE),
+        }
+    }
+}
+
+//Trace goes line by line to stderr, to mix with program output, or buffered to a file
+fn trace_output(dest: &ListDest) -> Box<dyn Write> {
+    match dest {
+        None => Box::new(io::LineWriter::new(io::stderr())),
+        Some(path) => match fs::File::create(path) {
+            Ok(f) => Box::new(io::BufWriter::new(f)),
+            Err(e) => {
+                eprintln!("Could not write trace file {path}, error {e}");
+                std::process::exit(EX_IOERR);
+            }
+        },
+    }
+}
+
+fn debug_program(opts: &Options, source: &Source, program: &Program) {
+    let res = debug(
+        program,
+        &source.text,
+        &opts.limits,
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+    );
+    if let Err(e) = res {
+        report(opts, &runtime_diagnostic(&e, program, &source.text), source);
+        std::process::exit(EX_SOFTWARE);
+    }
+}
+
+fn report(opts: &Options, d: &Diagnostic, source: &Source) {
+    match opts.error_format {
+        ErrorFormat::Human => eprintln!("{}", render(d, &source.text)),
+        ErrorFormat::Json => eprintln!("{}", to_json(d)),
+    }
 }
@@ -0,0 +1,368 @@
+//Object files for compiled programs, so that a program can be compiled once and run many times.
+//
+//Binary format, all integers little endian:
+//  magic     "P0C\0"
+//  version   u16
+//  flags     u16, bit 0 set when debug info follows the code
+//  entry     i32
+//  count     u32, number of instructions
+//  code      count * (fct u8, level i32, adr i32)
+//  debug info (optional):
+//    lines   count * i32
+//    name    u32 length + UTF-8 bytes, length 0 for no name
+//
+//Text format, one item per line, blank lines and lines starting with ';' are skipped:
+//  pl0r-object 1
+//  entry 0
+//  code 5
+//  jmp 0 1
+//  ...
+//  lines 1 1 2 2 3      (optional debug info)
+//  name gcd.pl0         (optional debug info)
+//
+//Scopes (names of procedures and variables) and statement addresses are not saved, they
+//are only known when running from source.
+
+use crate::defs::{fct_as_i32, fct_from_i32, Instruction, Program, MNEMONICS};
+use std::fmt;
+use std::fs;
+use std::io;
+
+pub const OBJ_MAGIC: &[u8; 4] = b"P0C\0";
+pub const OBJ_TEXT_MAGIC: &str = "pl0r-object";
+pub const OBJ_VERSION: u16 = 1;
+const FLAG_DEBUG: u16 = 1;
+
+#[derive(Debug)]
+pub enum ObjectError {
+    Io(io::Error),          //Reading or writing the file failed
+    BadMagic,               //Not an object file
+    BadVersion(u16),        //Object file from an unknown version
+    Truncated,              //File ends before all parts are read
+    BadInstruction(usize),  //Unknown function code at this address
+    BadEntry(i32),          //Entry point outside the code
+    BadText(usize, String), //Syntax error in text format at this line
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::Io(e) => write!(f, "I/O error: {e}"),
+            ObjectError::BadMagic => write!(f, "not a pl0r object file"),
+            ObjectError::BadVersion(v) => {
+                write!(f, "object file version {v}, expected {OBJ_VERSION}")
+            }
+            ObjectError::Truncated => write!(f, "object file is truncated"),
+            ObjectError::BadInstruction(adr) => write!(f, "bad instruction at address {adr}"),
+            ObjectError::BadEntry(adr) => write!(f, "entry point {adr} is outside the code"),
+            ObjectError::BadText(line, msg) => write!(f, "line {line}: {msg}"),
+        }
+    }
+}
+
+impl From<io::Error> for ObjectError {
+    fn from(e: io::Error) -> Self {
+        ObjectError::Io(e)
+    }
+}
+
+//Program in binary format, debug info only if asked for
+pub fn to_bytes(prog: &Program, debug: bool) -> Vec<u8> {
+    let debug = debug && prog.lines.len() == prog.code.len();
+    let mut out = Vec::new();
+    out.extend_from_slice(OBJ_MAGIC);
+    out.extend_from_slice(&OBJ_VERSION.to_le_bytes());
+    out.extend_from_slice(&(if debug { FLAG_DEBUG } else { 0 }).to_le_bytes());
+    out.extend_from_slice(&prog.entry.to_le_bytes());
+    out.extend_from_slice(&(prog.code.len() as u32).to_le_bytes());
+    for instr in &prog.code {
+        out.push(fct_as_i32(instr.fct) as u8);
+        out.extend_from_slice(&instr.level.to_le_bytes());
+        out.extend_from_slice(&instr.adr.to_le_bytes());
+    }
+    if debug {
+        for line in &prog.lines {
+            out.extend_from_slice(&line.to_le_bytes());
+        }
+        let name = prog.name.as_deref().unwrap_or("");
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+    }
+    out
+}
+
+//Reads binary format piece by piece
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+fn take<'a>(r: &mut Reader<'a>, n: usize) -> Result<&'a [u8], ObjectError> {
+    if r.bytes.len() - r.pos < n {
+        return Err(ObjectError::Truncated);
+    }
+    let part = &r.bytes[r.pos..r.pos + n];
+    r.pos += n;
+    Ok(part)
+}
+
+fn take_u16(r: &mut Reader) -> Result<u16, ObjectError> {
+    let b = take(r, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn take_i32(r: &mut Reader) -> Result<i32, ObjectError> {
+    let b = take(r, 4)?;
+    Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn take_u32(r: &mut Reader) -> Result<u32, ObjectError> {
+    let b = take(r, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Program, ObjectError> {
+    let r = &mut Reader { bytes, pos: 0 };
+    if take(r, 4).map_err(|_| ObjectError::BadMagic)? != OBJ_MAGIC {
+        return Err(ObjectError::BadMagic);
+    }
+    let version = take_u16(r)?;
+    if version != OBJ_VERSION {
+        return Err(ObjectError::BadVersion(version));
+    }
+    let flags = take_u16(r)?;
+    let entry = take_i32(r)?;
+    let count = take_u32(r)? as usize;
+
+    let mut code = Vec::new();
+    for cx in 0..count {
+        let f = take(r, 1)?[0] as i32;
+        let fct = fct_from_i32(f).ok_or(ObjectError::BadInstruction(cx))?;
+        let level = take_i32(r)?;
+        let adr = take_i32(r)?;
+        code.push(Instruction { fct, level, adr });
+    }
+
+    let mut lines = Vec::new();
+    let mut name = None;
+    if flags & FLAG_DEBUG != 0 {
+        for _i in 0..count {
+            lines.push(take_i32(r)?);
+        }
+        let len = take_u32(r)? as usize;
+        let text = String::from_utf8_lossy(take(r, len)?).into_owned();
+        if !text.is_empty() {
+            name = Some(text);
+        }
+    }
+    check_entry(Program {
+        code,
+        entry,
+        lines,
+        name,
+        scopes: Vec::new(),
+        stmts: Vec::new(),
+    })
+}
+
+fn check_entry(prog: Program) -> Result<Program, ObjectError> {
+    if prog.entry < 0 || prog.entry as usize >= prog.code.len() {
+        return Err(ObjectError::BadEntry(prog.entry));
+    }
+    Ok(prog)
+}
+
+//Program in text format, debug info only if asked for
+pub fn to_text(prog: &Program, debug: bool) -> String {
+    let mut out = format!("{OBJ_TEXT_MAGIC} {OBJ_VERSION}\n");
+    out.push_str(&format!("entry {}\n", prog.entry));
+    out.push_str(&format!("code {}\n", prog.code.len()));
+    for instr in &prog.code {
+        let mnemonic = MNEMONICS[fct_as_i32(instr.fct) as usize];
+        out.push_str(&format!("{mnemonic} {} {}\n", instr.level, instr.adr));
+    }
+    if debug && prog.lines.len() == prog.code.len() {
+        let lines: Vec<String> = prog.lines.iter().map(|l| l.to_string()).collect();
+        out.push_str(&format!("lines {}\n", lines.join(" ")));
+        if let Some(name) = &prog.name {
+            out.push_str(&format!("name {name}\n"));
+        }
+    }
+    out
+}
+
+fn parse_num(n: usize, word: Option<&str>) -> Result<i32, ObjectError> {
+    word.and_then(|w| w.parse::<i32>().ok())
+        .ok_or_else(|| ObjectError::BadText(n, "number expected".to_owned()))
+}
+
+pub fn from_text(text: &str) -> Result<Program, ObjectError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with(';'));
+
+    let (n, header) = lines.next().ok_or(ObjectError::BadMagic)?;
+    let mut words = header.split_whitespace();
+    if words.next() != Some(OBJ_TEXT_MAGIC) {
+        return Err(ObjectError::BadMagic);
+    }
+    let version = parse_num(n, words.next())?;
+    if version != OBJ_VERSION as i32 {
+        return Err(ObjectError::BadVersion(version as u16));
+    }
+
+    let mut prog = Program {
+        code: Vec::new(),
+        entry: 0,
+        lines: Vec::new(),
+        name: None,
+        scopes: Vec::new(),
+        stmts: Vec::new(),
+    };
+    let mut count = None;
+    for (n, line) in lines {
+        let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let mut words = rest.split_whitespace();
+        match key {
+            "entry" => prog.entry = parse_num(n, words.next())?,
+            "code" => count = Some(parse_num(n, words.next())? as usize),
+            "lines" => {
+                for w in words {
+                    prog.lines.push(parse_num(n, Some(w))?);
+                }
+            }
+            "name" => prog.name = Some(rest.trim().to_owned()),
+            m => match MNEMONICS.iter().position(|x| *x == m) {
+                Some(ind) => prog.code.push(Instruction {
+                    fct: fct_from_i32(ind as i32).unwrap(),
+                    level: parse_num(n, words.next())?,
+                    adr: parse_num(n, words.next())?,
+                }),
+                None => return Err(ObjectError::BadText(n, format!("unknown item {m}"))),
+            },
+        }
+    }
+    if count != Some(prog.code.len()) {
+        return Err(ObjectError::Truncated);
+    }
+    if !prog.lines.is_empty() && prog.lines.len() != prog.code.len() {
+        return Err(ObjectError::Truncated);
+    }
+    check_entry(prog)
+}
+
+//Write object file, binary or text
+pub fn save(path: &str, prog: &Program, text: bool, debug: bool) -> Result<(), ObjectError> {
+    if text {
+        fs::write(path, to_text(prog, debug))?;
+    } else {
+        fs::write(path, to_bytes(prog, debug))?;
+    }
+    Ok(())
+}
+
+//Read object file, format is recognised from its first bytes
+pub fn load(path: &str) -> Result<Program, ObjectError> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(OBJ_TEXT_MAGIC.as_bytes()) {
+        from_text(&String::from_utf8_lossy(&bytes))
+    } else {
+        from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //An example compiled from source, without the debug info object files leave out
+    fn example() -> Program {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/gcd.pl0");
+        let text = fs::read_to_string(path).unwrap();
+        let mut prog = crate::compile_source(&crate::Source::named("gcd.pl0", &text)).unwrap();
+        prog.scopes.clear();
+        prog.stmts.clear();
+        prog
+    }
+
+    fn stripped(prog: &Program) -> Program {
+        Program {
+            lines: Vec::new(),
+            name: None,
+            ..prog.clone()
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let prog = example();
+        assert_eq!(from_bytes(&to_bytes(&prog, true)).unwrap(), prog);
+        assert_eq!(
+            from_bytes(&to_bytes(&prog, false)).unwrap(),
+            stripped(&prog)
+        );
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let prog = example();
+        assert_eq!(from_text(&to_text(&prog, true)).unwrap(), prog);
+        assert_eq!(from_text(&to_text(&prog, false)).unwrap(), stripped(&prog));
+    }
+
+    #[test]
+    fn bad_binary() {
+        let bytes = to_bytes(&example(), true);
+        assert!(matches!(from_bytes(b"P0"), Err(ObjectError::BadMagic)));
+        assert!(matches!(
+            from_bytes(b"XXXX\x01\x00"),
+            Err(ObjectError::BadMagic)
+        ));
+        let mut other = bytes.clone();
+        other[4] = 9;
+        assert!(matches!(
+            from_bytes(&other),
+            Err(ObjectError::BadVersion(9))
+        ));
+        let cut = &bytes[..bytes.len() - 3];
+        assert!(matches!(from_bytes(cut), Err(ObjectError::Truncated)));
+        let mut bad = bytes.clone();
+        bad[16 + 9] = 42; //Function code of the second instruction
+        assert!(matches!(
+            from_bytes(&bad),
+            Err(ObjectError::BadInstruction(1))
+        ));
+        let mut entry = bytes;
+        entry[8] = 0xff; //Entry point -1
+        entry[9..12].copy_from_slice(&[0xff, 0xff, 0xff]);
+        assert!(matches!(from_bytes(&entry), Err(ObjectError::BadEntry(-1))));
+    }
+
+    fn text_error(text: &str) -> ObjectError {
+        from_text(text).unwrap_err()
+    }
+
+    #[test]
+    fn bad_text() {
+        let head = "pl0r-object 1\nentry 0\n";
+        assert!(matches!(text_error("object 1\n"), ObjectError::BadMagic));
+        assert!(matches!(
+            text_error("pl0r-object 2\n"),
+            ObjectError::BadVersion(2)
+        ));
+        let unknown = text_error(&format!("{head}code 1\nmov 0 1\n"));
+        assert!(matches!(unknown, ObjectError::BadText(4, m) if m == "unknown item mov"));
+        let number = text_error(&format!("{head}code 1\njmp 0 x\n"));
+        assert!(matches!(number, ObjectError::BadText(4, m) if m == "number expected"));
+        let missing = text_error(&format!("{head}code 1\njmp 0\n"));
+        assert!(matches!(missing, ObjectError::BadText(4, _)));
+        let count = text_error(&format!("{head}code 2\njmp 0 0\n"));
+        assert!(matches!(count, ObjectError::Truncated));
+        let lines = text_error(&format!("{head}code 1\njmp 0 0\nlines 1 2\n"));
+        assert!(matches!(lines, ObjectError::Truncated));
+        let entry = text_error("pl0r-object 1\nentry 5\ncode 1\njmp 0 0\n");
+        assert!(matches!(entry, ObjectError::BadEntry(5)));
+    }
+}
@@ -1,441 +1,718 @@
+use crate::ast::*;
+use crate::defs::{Dialect, ERR_MSGS};
+use crate::diagnostic::Diagnostic;
 use crate::scanner::{next_sym, Scanner};
 use crate::token::*;
-use crate::{defs::*, parse_error, scan_error};
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum ObjType {
-    Constant,
-    Variable,
-    Procedure,
-}
-
-const POS_NOT_FOUND: i32 = 0;
-
-#[derive(Clone, Debug)]
-struct ObjDesc {
-    name: String,
-    kind: ObjType,
-    val_or_lev: i32,
-    adr: i32,
-}
 
 pub struct Parser {
     s: Scanner,
     sym: Token,
-    table: Vec<ObjDesc>,        //Identifier table array (well, vector)
-    pub code: Vec<Instruction>, //Code array (well, vector)
-    cx: i32,                    //Code allocation index
-    line: i32,                  //For code listing output
+    span: Span,                 //Location of sym
+    prev_span: Span,            //Location of the last consumed symbol
+    pub diags: Vec<Diagnostic>, //Errors found during parser run
+    errpos: usize,              //Source position of last reported error
 }
 
 impl Parser {
-    pub fn new(src: &str) -> Self {
-        let s = Scanner::new(src);
+    pub fn new(source: &str, name: Option<&str>, dialect: Dialect) -> Self {
+        let s = Scanner::new(source, name, dialect);
+        let span = Span {
+            line: 1,
+            col: 1,
+            ..Span::default()
+        };
 
         Parser {
             s,
             sym: Token::WhiteSpace(' '),
-            table: Vec::with_capacity(ID_TABLE_LEN as usize),
-            code: Vec::with_capacity(CODE_ARR_SIZE as usize),
-            cx: 0,
-            line: 0,
+            span,
+            prev_span: span,
+            diags: Vec::new(),
+            errpos: usize::MAX,
         }
     }
 }
 
-pub fn get_one_sym(p: &mut Parser) -> Token {
-    let tok = next_sym(&mut p.s);
-    if p.line != p.s.line {
-        if p.line == 0 {
-            //first
-            print!("{:5} ", p.cx);
-            print!("{tok}");
-        } else {
-            //others
-            print!("{tok}");
-            print!("{:5} ", p.cx);
+//Errors are collected and parsing goes on, only the first error at a symbol is reported
+fn parse_error(p: &mut Parser, n: usize) {
+    if p.span.start != p.errpos {
+        let d = Diagnostic::new(p.s.name.as_deref(), Some(error_span(p)), n, ERR_MSGS[n]);
+        p.diags.push(d);
+        p.errpos = p.span.start;
+    }
+}
+
+//Where an error at sym is shown. The end of the file has no line to show, errors there
+//go right after the last symbol. Symbols are ASCII, their length in bytes is their
+//length in columns.
+fn error_span(p: &Parser) -> Span {
+    if p.sym != Token::Eof {
+        return p.span;
+    }
+    let last = p.prev_span;
+    Span {
+        col: last.col + (last.end - last.start) as i32,
+        start: last.end,
+        ..last
+    }
+}
+
+//Resync a'la Wirth: if sym is not in s1, report error n and skip symbols until one in s1 + s2.
+//At the end of the file there is nothing to skip, the caller reports what is missing.
+fn test(p: &mut Parser, s1: SymSet, s2: SymSet, n: usize) {
+    if !s1.contains(&p.sym) && p.sym != Token::Eof {
+        parse_error(p, n);
+        let stop = s1 | s2;
+        while !stop.contains(&p.sym) && p.sym != Token::Eof {
+            getsym(p);
         }
-        p.line = p.s.line;
-    } else {
-        print!("{tok}");
     }
-    tok
 }
 
 pub fn getsym(p: &mut Parser) {
+    p.prev_span = p.span;
     loop {
-        let tok = get_one_sym(p);
+        let (tok, span) = next_sym(&mut p.s);
         match tok {
             Token::WhiteSpace(_) => {}
             _ => {
                 p.sym = tok;
+                p.span = span;
                 break;
             }
         }
     }
 }
 
-fn gen(p: &mut Parser, x: Fct, y: i32, z: i32) {
-    let cx = p.cx as usize;
-    if cx > CODE_ARR_SIZE as usize {
-        scan_error(p.s.line, "program too long");
+//Span from the start of a node to the end of the last consumed symbol
+fn span_from(p: &Parser, start: Span) -> Span {
+    Span {
+        end: p.prev_span.end.max(start.start),
+        ..start
     }
-    p.code[cx].fct = x;
-    p.code[cx].level = y;
-    p.code[cx].adr = z;
-    p.cx += 1;
 }
 
-fn enter(p: &mut Parser, tx: &mut i32, name: String, k: ObjType, val_or_lev: i32, adr: &mut i32) {
-    *tx += 1;
-    let t = *tx as usize;
-    p.table[t].name = name;
-    p.table[t].kind = k;
-
-    match k {
-        ObjType::Constant => {
-            if val_or_lev > ADDR_MAX {
-                parse_error(p.s.line, 30);
-            }
-            p.table[t].val_or_lev = val_or_lev;
-            p.table[t].adr = 0;
-        }
-        ObjType::Variable => {
-            p.table[t].val_or_lev = val_or_lev;
-            p.table[t].adr = *adr;
-            *adr += 1;
-        }
-        ObjType::Procedure => {
-            p.table[t].val_or_lev = val_or_lev;
-            p.table[t].adr = 0;
-        }
+//Zero width span at the current symbol, for nodes made up after an error
+fn here(p: &Parser) -> Span {
+    Span {
+        end: p.span.start,
+        ..p.span
     }
 }
 
-fn position(p: &mut Parser, tx: i32, id: &String) -> i32 {
-    let mut i: i32;
-
-    p.table[POS_NOT_FOUND as usize].name = id.clone();
-    i = tx;
-    while p.table[i as usize].name != *id {
-        i = i - 1;
-    }
-    i
+fn ident(p: &Parser, name: String) -> Ident {
+    Ident { name, span: p.span }
 }
 
-fn constdeclaration(p: &mut Parser, tx: &mut i32) {
+fn constdeclaration(p: &mut Parser, decls: &mut Vec<Decl>) {
+    let start = p.span;
     match p.sym.clone() {
         Token::Identifier(id) => {
+            let name = ident(p, id);
             getsym(p);
             if p.sym == Token::Equal || p.sym == Token::Becomes {
                 if p.sym == Token::Becomes {
-                    parse_error(p.s.line, 1);
+                    parse_error(p, 1);
                 }
                 getsym(p);
                 match p.sym {
                     Token::Number(n) => {
-                        enter(p, tx, id, ObjType::Constant, n, &mut 0);
+                        let value = p.span;
                         getsym(p);
+                        decls.push(Decl {
+                            kind: DeclKind::Const(name, n, value),
+                            span: span_from(p, start),
+                        });
                     }
-                    _ => parse_error(p.s.line, 2),
+                    _ => parse_error(p, 2),
                 }
             } else {
-                parse_error(p.s.line, 3);
+                parse_error(p, 3);
             }
         }
-        _ => parse_error(p.s.line, 4),
+        _ => parse_error(p, 4),
     }
 }
 
-fn vardeclaration(p: &mut Parser, lev: i32, tx: &mut i32, dx: &mut i32) {
+fn vardeclaration(p: &mut Parser, decls: &mut Vec<Decl>) {
     match p.sym.clone() {
         Token::Identifier(s) => {
-            enter(p, tx, s, ObjType::Variable, lev, dx);
+            let name = ident(p, s);
             getsym(p);
+            decls.push(Decl {
+                span: name.span,
+                kind: DeclKind::Var(name),
+            });
         }
-        _ => parse_error(p.s.line, 4),
+        _ => parse_error(p, 4),
     }
 }
 
-fn fct_as_i32(f: Fct) -> i32 {
-    match f {
-        Fct::Lit => 0,
-        Fct::Opr => 1,
-        Fct::Lod => 2,
-        Fct::Sto => 3,
-        Fct::Cal => 4,
-        Fct::Int => 5,
-        Fct::Jmp => 6,
-        Fct::Jpc => 7,
-    }
-}
-
-fn listcode(p: &Parser, cx0: i32, cx: i32) {
-    //List code generated for this block (actually, incrementally for *every* block)
-    println!();
-    for i in cx0..cx {
-        let ind = fct_as_i32(p.code[i as usize].fct);
-        println!(
-            "{i:>5}{:>5}{:>3}{:>5}",
-            MNEMONICS[ind as usize], p.code[i as usize].level, p.code[i as usize].adr
-        );
-    }
-}
-
-fn expression(p: &mut Parser, lev: i32, tx: i32) {
+fn expression(p: &mut Parser, fsys: SymSet) -> Expr {
+    let start = p.span;
+    let fsys1 = fsys | SymSet::new(&[Token::Plus, Token::Minus]);
+    let mut e;
     if p.sym == Token::Plus || p.sym == Token::Minus {
         let addop = p.sym.clone();
         getsym(p);
-        term(p, lev, tx);
+        e = term(p, fsys1);
         if addop == Token::Minus {
-            gen(p, Fct::Opr, 0, 1);
+            e = Expr {
+                kind: ExprKind::Neg(Box::new(e)),
+                span: span_from(p, start),
+            };
         }
     } else {
-        term(p, lev, tx);
+        e = term(p, fsys1);
     }
+    expression_rest(p, fsys1, e)
+}
 
+//Terms added to or subtracted from e
+fn expression_rest(p: &mut Parser, fsys1: SymSet, mut e: Expr) -> Expr {
+    let start = e.span;
     while p.sym == Token::Plus || p.sym == Token::Minus {
-        let addop = p.sym.clone();
-        getsym(p);
-        term(p, lev, tx);
-        if addop == Token::Plus {
-            gen(p, Fct::Opr, 0, 2);
+        let op = if p.sym == Token::Plus {
+            BinOp::Add
         } else {
-            gen(p, Fct::Opr, 0, 3);
-        }
+            BinOp::Sub
+        };
+        getsym(p);
+        let right = term(p, fsys1);
+        e = Expr {
+            kind: ExprKind::Binary(op, Box::new(e), Box::new(right)),
+            span: span_from(p, start),
+        };
     }
+    e
+}
+
+//An expression whose first factor, a parenthesised one, has been parsed already
+fn expression_from(p: &mut Parser, fsys: SymSet, first: Expr) -> Expr {
+    let fsys1 = fsys | SymSet::new(&[Token::Plus, Token::Minus]);
+    let e = term_rest(p, fsys1 | SymSet::new(&[Token::Times, Token::Slash]), first);
+    expression_rest(p, fsys1, e)
 }
 
-fn condition(p: &mut Parser, lev: i32, tx: i32) {
-    if p.sym == Token::OddSym {
+fn relsys() -> SymSet {
+    SymSet::new(&[
+        Token::Equal,
+        Token::NotEqual,
+        Token::Less,
+        Token::LessEqual,
+        Token::Greater,
+        Token::GreaterEqual,
+    ])
+}
+
+//condition = conjunction {"or" conjunction}
+fn condition(p: &mut Parser, fsys: SymSet) -> Cond {
+    let c = conjunction(p, fsys | SymSet::new(&[Token::OrSym]));
+    condition_rest(p, fsys, c)
+}
+
+fn condition_rest(p: &mut Parser, fsys: SymSet, mut c: Cond) -> Cond {
+    let start = c.span;
+    let fsys1 = fsys | SymSet::new(&[Token::OrSym]);
+    while p.sym == Token::OrSym {
         getsym(p);
-        expression(p, lev, tx);
-        gen(p, Fct::Opr, 0, 6);
-    } else {
-        expression(p, lev, tx);
-        if !sym_relational(&p.sym) {
-            parse_error(p.s.line, 20)
-        } else {
-            let relop = p.sym.clone();
+        let right = conjunction(p, fsys1);
+        c = Cond {
+            kind: CondKind::Or(Box::new(c), Box::new(right)),
+            span: span_from(p, start),
+        };
+    }
+    c
+}
+
+//conjunction = negation {"and" negation}
+fn conjunction(p: &mut Parser, fsys: SymSet) -> Cond {
+    let c = negation(p, fsys | SymSet::new(&[Token::AndSym]));
+    conjunction_rest(p, fsys, c)
+}
+
+fn conjunction_rest(p: &mut Parser, fsys: SymSet, mut c: Cond) -> Cond {
+    let start = c.span;
+    let fsys1 = fsys | SymSet::new(&[Token::AndSym]);
+    while p.sym == Token::AndSym {
+        getsym(p);
+        let right = negation(p, fsys1);
+        c = Cond {
+            kind: CondKind::And(Box::new(c), Box::new(right)),
+            span: span_from(p, start),
+        };
+    }
+    c
+}
+
+//negation = "not" negation | "odd" expression | "(" condition ")" | relation
+fn negation(p: &mut Parser, fsys: SymSet) -> Cond {
+    let start = p.span;
+    match p.sym {
+        Token::NotSym => {
             getsym(p);
-            expression(p, lev, tx);
-            match relop {
-                Token::Equal => gen(p, Fct::Opr, 0, 8),
-                Token::NotEqual => gen(p, Fct::Opr, 0, 9),
-                Token::Less => gen(p, Fct::Opr, 0, 10),
-                Token::GreaterEqual => gen(p, Fct::Opr, 0, 11),
-                Token::Greater => gen(p, Fct::Opr, 0, 12),
-                Token::LessEqual => gen(p, Fct::Opr, 0, 13),
-                _ => parse_error(p.s.line, 28),
+            let c = negation(p, fsys);
+            Cond {
+                kind: CondKind::Not(Box::new(c)),
+                span: span_from(p, start),
+            }
+        }
+        Token::OddSym => {
+            getsym(p);
+            let e = expression(p, fsys);
+            Cond {
+                kind: CondKind::Odd(e),
+                span: span_from(p, start),
+            }
+        }
+        Token::LParen => match parenthesised(p, fsys) {
+            Operand::Cond(c) => c,
+            Operand::Expr(e) => {
+                let left = expression_from(p, fsys | relsys(), e);
+                relation(p, fsys, left)
             }
+        },
+        _ => {
+            let left = expression(p, fsys | relsys());
+            relation(p, fsys, left)
+        }
+    }
+}
+
+//Comparison of left with the expression after a relational operator
+fn relation(p: &mut Parser, fsys: SymSet, left: Expr) -> Cond {
+    let start = left.span;
+    let mut op = RelOp::Eq;
+    let right;
+    if !sym_relational(&p.sym) {
+        parse_error(p, 20);
+        right = Expr {
+            kind: ExprKind::Number(0),
+            span: here(p),
+        };
+    } else {
+        match p.sym {
+            Token::Equal => op = RelOp::Eq,
+            Token::NotEqual => op = RelOp::Ne,
+            Token::Less => op = RelOp::Lt,
+            Token::GreaterEqual => op = RelOp::Ge,
+            Token::Greater => op = RelOp::Gt,
+            Token::LessEqual => op = RelOp::Le,
+            _ => parse_error(p, 28),
+        }
+        getsym(p);
+        right = expression(p, fsys);
+    }
+    Cond {
+        kind: CondKind::Compare(op, left, right),
+        span: span_from(p, start),
+    }
+}
+
+//What a parenthesis in a condition holds, found out only at its operators
+enum Operand {
+    Cond(Cond),
+    Expr(Expr),
+}
+
+//"(" condition ")" or "(" expression ")", the start of a condition either way
+fn parenthesised(p: &mut Parser, fsys: SymSet) -> Operand {
+    let start = p.span;
+    getsym(p);
+    let fsys1 = fsys | SymSet::new(&[Token::RParen, Token::AndSym, Token::OrSym]);
+    let inner = match p.sym {
+        Token::LParen => match parenthesised(p, fsys1 | relsys()) {
+            Operand::Expr(e) => Operand::Expr(expression_from(p, fsys1 | relsys(), e)),
+            c => c,
+        },
+        Token::NotSym | Token::OddSym => Operand::Cond(negation(p, fsys1)),
+        _ => Operand::Expr(expression(p, fsys1 | relsys())),
+    };
+    let inner = match inner {
+        Operand::Expr(e) if sym_relational(&p.sym) => Operand::Cond(relation(p, fsys1, e)),
+        other => other,
+    };
+    let inner = match inner {
+        Operand::Cond(c) => {
+            let c = conjunction_rest(p, fsys1, c);
+            Operand::Cond(condition_rest(p, fsys1, c))
         }
+        other => other,
+    };
+    if p.sym == Token::RParen {
+        getsym(p);
+    } else {
+        parse_error(p, 22);
+    }
+    match inner {
+        Operand::Cond(c) => Operand::Cond(Cond {
+            kind: c.kind,
+            span: span_from(p, start),
+        }),
+        Operand::Expr(e) => Operand::Expr(Expr {
+            kind: e.kind,
+            span: span_from(p, start),
+        }),
     }
 }
 
-fn factor(p: &mut Parser, lev: i32, tx: i32) {
-    while sym_in_facbegsys(&p.sym) {
-        match p.sym.clone() {
+fn factor(p: &mut Parser, fsys: SymSet) -> Expr {
+    let mut result = None;
+    test(p, facbegsys(), fsys, 24);
+    //Wirth goes on with the next factor after error 23, only the first one is kept
+    while facbegsys().contains(&p.sym) {
+        let start = p.span;
+        let kind = match p.sym.clone() {
             Token::Identifier(s) => {
-                let i = position(p, tx, &s);
-                if i == POS_NOT_FOUND {
-                    parse_error(p.s.line, 11);
-                } else {
-                    let v = p.table[i as usize].clone();
-                    match v.kind {
-                        ObjType::Constant => gen(p, Fct::Lit, 0, v.val_or_lev),
-                        ObjType::Variable => gen(p, Fct::Lod, lev - v.val_or_lev, v.adr),
-                        ObjType::Procedure => parse_error(p.s.line, 21),
-                    }
-                }
+                let name = ident(p, s);
                 getsym(p);
+                ExprKind::Name(name)
             }
             Token::Number(n) => {
-                let mut num = n;
-                if num > ADDR_MAX {
-                    parse_error(p.s.line, 30);
-                    num = 0;
-                }
-                gen(p, Fct::Lit, 0, num);
                 getsym(p);
+                ExprKind::Number(n)
             }
             Token::LParen => {
                 getsym(p);
-                expression(p, lev, tx);
+                let e = expression(p, fsys | SymSet::new(&[Token::RParen]));
                 if p.sym == Token::RParen {
                     getsym(p);
                 } else {
-                    parse_error(p.s.line, 22);
+                    parse_error(p, 22);
                 }
+                e.kind
             }
-            _ => {}
+            _ => ExprKind::Number(0),
+        };
+        if result.is_none() {
+            result = Some(Expr {
+                kind,
+                span: span_from(p, start),
+            });
         }
+        test(p, fsys, SymSet::new(&[Token::LParen]), 23);
     }
+    result.unwrap_or_else(|| Expr {
+        kind: ExprKind::Number(0),
+        span: here(p),
+    })
+}
+
+fn term(p: &mut Parser, fsys: SymSet) -> Expr {
+    let fsys1 = fsys | SymSet::new(&[Token::Times, Token::Slash]);
+    let e = factor(p, fsys1);
+    term_rest(p, fsys1, e)
 }
 
-fn term(p: &mut Parser, lev: i32, tx: i32) {
-    factor(p, lev, tx);
+//Factors multiplying or dividing e
+fn term_rest(p: &mut Parser, fsys1: SymSet, mut e: Expr) -> Expr {
+    let start = e.span;
     while p.sym == Token::Times || p.sym == Token::Slash {
-        let mulop = p.sym.clone();
+        let op = if p.sym == Token::Times {
+            BinOp::Mul
+        } else {
+            BinOp::Div
+        };
         getsym(p);
-        factor(p, lev, tx);
-        if mulop == Token::Times {
-            gen(p, Fct::Opr, 0, 4);
+        let right = factor(p, fsys1);
+        e = Expr {
+            kind: ExprKind::Binary(op, Box::new(e), Box::new(right)),
+            span: span_from(p, start),
+        };
+    }
+    e
+}
+
+//Statements separated by semicolons, of begin ... end and repeat ... until
+fn statements(p: &mut Parser, fsys: SymSet) -> Vec<Stmt> {
+    let mut stmts = vec![statement(p, fsys)];
+    let stmtsys = statbegsys() | SymSet::new(&[Token::Semicolon, Token::Identifier(String::new())]);
+    while stmtsys.contains(&p.sym) {
+        if p.sym == Token::Semicolon {
+            getsym(p);
         } else {
-            gen(p, Fct::Opr, 0, 5);
+            parse_error(p, 10);
         }
+        stmts.push(statement(p, fsys));
     }
+    stmts
 }
 
-fn statement(p: &mut Parser, lev: i32, tx: i32) {
-    match p.sym.clone() {
+//Statement of a then or a case label, empty if an else follows at once
+fn branch(p: &mut Parser, fsys: SymSet) -> Stmt {
+    if p.sym == Token::ElseSym {
+        return Stmt {
+            kind: StmtKind::Empty,
+            span: here(p),
+        };
+    }
+    statement(p, fsys)
+}
+
+//Labels, a colon and the statement of one case
+fn case_arm(p: &mut Parser, fsys: SymSet) -> CaseArm {
+    let start = p.span;
+    let labelsys = SymSet::new(&[Token::Comma, Token::DotDot, Token::Colon]);
+    let mut labels = Vec::new();
+    loop {
+        let label_start = p.span;
+        let low = expression(p, fsys | labelsys);
+        let high = if p.sym == Token::DotDot {
+            getsym(p);
+            Some(expression(p, fsys | labelsys))
+        } else {
+            None
+        };
+        labels.push(CaseLabel {
+            low,
+            high,
+            span: span_from(p, label_start),
+        });
+        if p.sym == Token::Comma {
+            getsym(p);
+        } else {
+            break;
+        }
+    }
+    if p.sym == Token::Colon {
+        getsym(p);
+    } else {
+        parse_error(p, 43);
+    }
+    let body = branch(p, fsys);
+    CaseArm {
+        labels,
+        body,
+        span: span_from(p, start),
+    }
+}
+
+fn statement(p: &mut Parser, fsys: SymSet) -> Stmt {
+    let start = p.span;
+    let kind = match p.sym.clone() {
         Token::Identifier(s) => {
-            let i = position(p, tx, &s);
-            if i == POS_NOT_FOUND {
-                parse_error(p.s.line, 11);
+            let name = ident(p, s);
+            getsym(p);
+            if p.sym == Token::Becomes {
+                getsym(p);
             } else {
-                let v = p.table[i as usize].clone();
-                match v.kind {
-                    ObjType::Constant | ObjType::Procedure => {
-                        //Assignment to non-variable
-                        parse_error(p.s.line, 12);
-                    }
-                    ObjType::Variable => {
-                        getsym(p);
-                        if p.sym == Token::Becomes {
-                            getsym(p);
-                        } else {
-                            parse_error(p.s.line, 13);
-                        }
-                        expression(p, lev, tx);
-                        gen(p, Fct::Sto, lev - v.val_or_lev, v.adr);
-                    }
-                }
+                parse_error(p, 13);
             }
+            StmtKind::Assign(name, expression(p, fsys))
         }
         Token::IfSym => {
             getsym(p);
-            condition(p, lev, tx);
+            let cond = condition(p, fsys | SymSet::new(&[Token::ThenSym, Token::DoSym]));
             if p.sym == Token::ThenSym {
                 getsym(p)
             } else {
-                parse_error(p.s.line, 16);
+                parse_error(p, 16);
             }
-            let cx1 = p.cx;
-            gen(p, Fct::Jpc, 0, 0);
-            statement(p, lev, tx);
-            p.code[cx1 as usize].adr = p.cx;
+            //An else belongs to the nearest if without one, as in Pascal
+            let then = branch(p, fsys | SymSet::new(&[Token::ElseSym]));
+            let otherwise = if p.sym == Token::ElseSym {
+                let keyword = p.span;
+                getsym(p);
+                Some((keyword, Box::new(statement(p, fsys))))
+            } else {
+                None
+            };
+            StmtKind::If(cond, Box::new(then), otherwise)
         }
         Token::CallSym => {
             getsym(p);
             match p.sym.clone() {
                 Token::Identifier(s) => {
-                    let i = position(p, tx, &s);
-                    if i == POS_NOT_FOUND {
-                        parse_error(p.s.line, 11);
-                    } else {
-                        let v = p.table[i as usize].clone();
-                        match v.kind {
-                            ObjType::Procedure => {
-                                gen(p, Fct::Cal, lev - v.val_or_lev, v.adr);
-                            }
-                            _ => parse_error(p.s.line, 15),
-                        }
-                    }
+                    let name = ident(p, s);
                     getsym(p);
+                    StmtKind::Call(name)
+                }
+                _ => {
+                    parse_error(p, 14);
+                    StmtKind::Empty
                 }
-                _ => parse_error(p.s.line, 14),
             }
         }
         Token::BeginSym => {
             getsym(p);
-            statement(p, lev, tx);
-            while sym_in_statbegsys_plus_semicolon(&p.sym) {
+            let stmts = statements(p, fsys | SymSet::new(&[Token::Semicolon, Token::EndSym]));
+            if p.sym == Token::EndSym {
+                getsym(p);
+            } else {
+                parse_error(p, 17);
+            }
+            StmtKind::Begin(stmts)
+        }
+        Token::WhileSym => {
+            getsym(p);
+            let cond = condition(p, fsys | SymSet::new(&[Token::DoSym]));
+            if p.sym == Token::DoSym {
+                getsym(p);
+            } else {
+                parse_error(p, 18);
+            }
+            StmtKind::While(cond, Box::new(statement(p, fsys)))
+        }
+        Token::RepeatSym => {
+            getsym(p);
+            let fsys1 = fsys | SymSet::new(&[Token::Semicolon, Token::UntilSym]);
+            let stmts = statements(p, fsys1);
+            if p.sym == Token::UntilSym {
+                getsym(p);
+            } else {
+                parse_error(p, 37);
+            }
+            StmtKind::Repeat(stmts, condition(p, fsys))
+        }
+        Token::CaseSym => {
+            getsym(p);
+            let expr = expression(p, fsys | SymSet::new(&[Token::OfSym]));
+            if p.sym == Token::OfSym {
+                getsym(p);
+            } else {
+                parse_error(p, 42);
+            }
+            let endsys = SymSet::new(&[Token::ElseSym, Token::EndSym]);
+            let fsys1 = fsys | endsys | SymSet::new(&[Token::Semicolon]);
+            let mut arms = Vec::new();
+            while !endsys.contains(&p.sym) && p.sym != Token::Eof {
+                arms.push(case_arm(p, fsys1));
                 if p.sym == Token::Semicolon {
                     getsym(p);
                 } else {
-                    parse_error(p.s.line, 10);
+                    break;
                 }
-                statement(p, lev, tx);
             }
+            let otherwise = if p.sym == Token::ElseSym {
+                let keyword = p.span;
+                getsym(p);
+                let otherwise = statement(p, fsys1);
+                if p.sym == Token::Semicolon {
+                    getsym(p);
+                }
+                Some((keyword, Box::new(otherwise)))
+            } else {
+                None
+            };
             if p.sym == Token::EndSym {
                 getsym(p);
             } else {
-                parse_error(p.s.line, 17);
+                parse_error(p, 17);
+            }
+            StmtKind::Case {
+                expr,
+                arms,
+                otherwise,
             }
         }
-        Token::WhileSym => {
-            let cx1 = p.cx;
+        Token::ForSym => {
             getsym(p);
-            condition(p, lev, tx);
-            let cx2 = p.cx as usize;
-            gen(p, Fct::Jpc, 0, 0);
+            let var = match p.sym.clone() {
+                Token::Identifier(s) => {
+                    let name = ident(p, s);
+                    getsym(p);
+                    Some(name)
+                }
+                _ => {
+                    parse_error(p, 38);
+                    None
+                }
+            };
+            if p.sym == Token::Becomes {
+                getsym(p);
+            } else {
+                parse_error(p, 13);
+            }
+            let limitsys = SymSet::new(&[Token::ToSym, Token::DowntoSym, Token::DoSym]);
+            let from = expression(p, fsys | limitsys);
+            let down = match p.sym {
+                Token::ToSym => false,
+                Token::DowntoSym => true,
+                _ => {
+                    parse_error(p, 39);
+                    false
+                }
+            };
+            if p.sym == Token::ToSym || p.sym == Token::DowntoSym {
+                getsym(p);
+            }
+            let to = expression(p, fsys | SymSet::new(&[Token::BySym, Token::DoSym]));
+            let by = if p.sym == Token::BySym {
+                getsym(p);
+                Some(expression(p, fsys | SymSet::new(&[Token::DoSym])))
+            } else {
+                None
+            };
             if p.sym == Token::DoSym {
                 getsym(p);
             } else {
-                parse_error(p.s.line, 18);
+                parse_error(p, 18);
+            }
+            let body = Box::new(statement(p, fsys));
+            match var {
+                Some(var) => StmtKind::For {
+                    var,
+                    from,
+                    down,
+                    to,
+                    by,
+                    body,
+                },
+                None => StmtKind::Empty,
             }
-            statement(p, lev, tx);
-            gen(p, Fct::Jmp, 0, cx1);
-            p.code[cx2].adr = p.cx;
         }
         Token::WriteSym => {
             getsym(p);
-            expression(p, lev, tx);
-            gen(p, Fct::Opr, 0, 15);
+            StmtKind::Write(expression(p, fsys))
         }
         Token::ReadSym => {
             getsym(p);
             match p.sym.clone() {
                 Token::Identifier(s) => {
-                    let i = position(p, tx, &s);
-                    if i == POS_NOT_FOUND {
-                        parse_error(p.s.line, 11);
-                    } else {
-                        gen(p, Fct::Opr, 0, 14);
-                        let v = p.table[i as usize].clone();
-                        match v.kind {
-                            ObjType::Variable => {
-                                gen(p, Fct::Sto, lev - v.val_or_lev, v.adr);
-                            }
-                            _ => parse_error(p.s.line, 27),
-                        }
-                    }
+                    let name = ident(p, s);
                     getsym(p);
+                    StmtKind::Read(name)
+                }
+                _ => {
+                    parse_error(p, 26);
+                    StmtKind::Empty
                 }
-                _ => parse_error(p.s.line, 26),
             }
         }
-        _ => {}
-    }
+        Token::ElseSym => {
+            //Parsed on as if the else was right, to find the errors after it
+            parse_error(p, 36);
+            getsym(p);
+            statement(p, fsys).kind
+        }
+        _ => StmtKind::Empty,
+    };
+    let span = match kind {
+        StmtKind::Empty => here(p),
+        _ => span_from(p, start),
+    };
+    test(p, fsys, SymSet::EMPTY, 19);
+    Stmt { kind, span }
 }
 
-pub fn block(p: &mut Parser, lev: i32, mut tx: i32) {
-    let mut dx: i32; //data allocation index
-    let tx0: i32; //initial table index
-    let cx0: i32; //Initial code index
-
-    dx = 3;
-    tx0 = tx;
-    p.table[tx as usize].adr = p.cx;
-    gen(p, Fct::Jmp, 0, 0);
-    if lev > MAX_BLOCK_NESTING {
-        parse_error(p.s.line, 32);
-    }
+pub fn block(p: &mut Parser, fsys: SymSet) -> Block {
+    let start = p.span;
+    let mut decls = Vec::new();
 
     loop {
         if p.sym == Token::ConstSym {
             getsym(p);
             loop {
-                constdeclaration(p, &mut tx);
+                constdeclaration(p, &mut decls);
                 while p.sym == Token::Comma {
                     getsym(p);
-                    constdeclaration(p, &mut tx);
+                    constdeclaration(p, &mut decls);
                 }
 
                 if p.sym == Token::Semicolon {
                     getsym(p);
                 } else {
-                    parse_error(p.s.line, 5);
+                    parse_error(p, 5);
                 }
 
                 match &p.sym {
@@ -448,16 +725,16 @@ pub fn block(p: &mut Parser, lev: i32, mut tx: i32) {
         if p.sym == Token::VarSym {
             getsym(p);
             loop {
-                vardeclaration(p, lev, &mut tx, &mut dx);
+                vardeclaration(p, &mut decls);
                 while p.sym == Token::Comma {
                     getsym(p);
-                    vardeclaration(p, lev, &mut tx, &mut dx);
+                    vardeclaration(p, &mut decls);
                 }
 
                 if p.sym == Token::Semicolon {
                     getsym(p);
                 } else {
-                    parse_error(p.s.line, 5);
+                    parse_error(p, 5);
                 }
 
                 match &p.sym {
@@ -468,71 +745,123 @@ pub fn block(p: &mut Parser, lev: i32, mut tx: i32) {
         }
 
         while p.sym == Token::ProcSym {
+            let proc_start = p.span;
             getsym(p);
 
-            match p.sym.clone() {
+            let name = match p.sym.clone() {
                 Token::Identifier(n) => {
-                    enter(p, &mut tx, n, ObjType::Procedure, lev, &mut dx);
+                    let name = ident(p, n);
                     getsym(p);
+                    Some(name)
                 }
-                _ => parse_error(p.s.line, 4),
-            }
+                _ => {
+                    parse_error(p, 4);
+                    None
+                }
+            };
 
             if p.sym == Token::Semicolon {
                 getsym(p)
             } else {
-                parse_error(p.s.line, 5);
+                parse_error(p, 5);
             }
 
-            block(p, lev + 1, tx);
+            let body = block(p, fsys | SymSet::new(&[Token::Semicolon]));
+            if let Some(name) = name {
+                decls.push(Decl {
+                    kind: DeclKind::Procedure(name, Box::new(body)),
+                    span: span_from(p, proc_start),
+                });
+            }
 
             if p.sym == Token::Semicolon {
                 getsym(p);
+                let procsys = SymSet::new(&[Token::Identifier(String::new()), Token::ProcSym]);
+                test(p, statbegsys() | procsys, fsys, 6);
             } else {
-                parse_error(p.s.line, 5);
+                parse_error(p, 5);
             }
         }
 
-        if !sym_in_declbegsys(&p.sym) {
+        let identsys = SymSet::new(&[Token::Identifier(String::new())]);
+        test(p, statbegsys() | identsys, declbegsys(), 7);
+        if !declbegsys().contains(&p.sym) {
             break;
         }
     }
 
-    p.code[p.table[tx0 as usize].adr as usize].adr = p.cx;
-    p.table[tx0 as usize].adr = p.cx;
-    cx0 = 0;
-    gen(p, Fct::Int, 0, dx);
-    statement(p, lev, tx);
-    gen(p, Fct::Opr, 0, 0); //return
-    listcode(p, cx0, p.cx);
+    let body = statement(p, fsys | SymSet::new(&[Token::Semicolon, Token::EndSym]));
+    let end = p.prev_span;
+    let span = span_from(p, start);
+    test(p, fsys, SymSet::EMPTY, 8);
+    Block {
+        decls,
+        body,
+        span,
+        end,
+    }
 }
 
-fn init_vecs(p: &mut Parser) {
-    for _i in 0..CODE_ARR_SIZE {
-        p.code.push(Instruction {
-            fct: Fct::Lit,
-            level: 0,
-            adr: 0,
-        });
+//Parse a whole program, errors of scanner and parser are left in p.diags in source order
+pub fn parser_run(p: &mut Parser) -> Program {
+    getsym(p);
+    let start = p.span;
+    let fsys = declbegsys() | statbegsys() | SymSet::new(&[Token::Period]);
+    let block = block(p, fsys);
+
+    if p.sym != Token::Period {
+        parse_error(p, 9);
+    }
+    let mut errors = std::mem::take(&mut p.s.errors);
+    errors.append(&mut p.diags);
+    errors.sort_by_key(|d| d.span.map_or(0, |s| s.start));
+    p.diags = errors;
+    if p.sym == Token::Period {
+        getsym(p);
     }
-    for _i in 0..ID_TABLE_LEN {
-        p.table.push(ObjDesc {
-            name: "".to_owned(),
-            kind: ObjType::Constant,
-            val_or_lev: 0,
-            adr: 0,
-        })
+    Program {
+        block,
+        span: span_from(p, start),
+        comments: std::mem::take(&mut p.s.comments),
     }
 }
 
-pub fn parser_run(mut p: Parser) -> Parser {
-    init_vecs(&mut p);
-    getsym(&mut p);
-    block(&mut p, 0, 0);
+#[cfg(test)]
+mod tests {
+    //Error numbers with line and column of a source that does not compile
+    fn errors(src: &str) -> Vec<(usize, i32, i32)> {
+        let diags = crate::compile(src).unwrap_err();
+        diags
+            .iter()
+            .map(|d| {
+                let span = d.span.unwrap();
+                (d.code, span.line, span.col)
+            })
+            .collect()
+    }
 
-    if p.sym != Token::Period {
-        println!("End of parser run: {:?}", p.sym);
-        parse_error(p.s.line, 9);
+    //A source cut short is reported once, right after its last symbol, with what is
+    //missing there
+    #[test]
+    fn truncated_sources() {
+        assert_eq!(errors(""), vec![(9, 1, 1)]);
+        assert_eq!(errors("var x;\nbegin\n  x := 1\nend\n"), vec![(9, 4, 4)]);
+        assert_eq!(errors("var x;\nbegin ! x"), vec![(17, 2, 10)]);
+        assert_eq!(errors("var x;\nbegin if x > 1"), vec![(16, 2, 15)]);
+        assert_eq!(errors("var x;\nbegin x := x +"), vec![(17, 2, 15)]);
+        assert_eq!(errors("var x;\nbegin x := (1 + 2"), vec![(22, 2, 18)]);
+        assert_eq!(errors("begin { open"), vec![(17, 1, 6), (35, 1, 7)]);
+        assert_eq!(errors("const a ="), vec![(2, 1, 10)]);
+    }
+
+    #[test]
+    fn else_after_empty_branch() {
+        assert!(crate::compile("var a;\nbegin if a = 1 then else a := 2 end.").is_ok());
+        assert!(crate::compile("var a;\nbegin case a of 1: else a := 2 end end.").is_ok());
+        assert_eq!(
+            errors("var a;\nbegin if a = 1 then a := 1; else a := 2 end."),
+            vec![(36, 2, 29)]
+        );
+        assert_eq!(errors("var a;\nbegin else a := 2 end."), vec![(36, 2, 7)]);
     }
-    p
 }
@@ -0,0 +1,704 @@
+//Pretty-printer: prints a syntax tree in the canonical layout. Two spaces per level,
+//one statement per line, `begin` on the line of its `then` or `do`, declarations one
+//kind per line with the `=` of constants aligned. A blank line between statements or
+//declarations in the source is kept, several blank lines become one. Relational
+//operators are written <=, >= and <>, or [, ] and # in the classic dialect.
+//Comments are not in the tree, they are put back by position: a comment on the line
+//where a statement or declaration ends stays at the end of that line, one inside a
+//statement stays in its text before the next symbol, others get a line of their own
+//before the next node.
+
+use crate::ast::*;
+use crate::defs::Dialect;
+use crate::token::Span;
+
+const INDENT: usize = 2; //Spaces per nesting level
+const WIDTH: usize = 80; //Variable lists are wrapped to this width
+
+struct Printer<'a> {
+    src: &'a str,         //Source of the tree, for comments and blank lines
+    comments: &'a [Span], //Comments of the source in order
+    dialect: Dialect,     //Operators are printed in this dialect
+    next: usize,          //First comment not printed yet
+    last: usize,          //Source position after the last node or comment printed
+    out: String,
+}
+
+fn pad(pr: &mut Printer, indent: usize) {
+    pr.out.push_str(&" ".repeat(indent));
+}
+
+//A blank line before a node or comment that follows an empty line in the source
+fn blank_line(pr: &mut Printer, next: usize) {
+    let between = pr.src.get(pr.last..next).unwrap_or("");
+    if between.matches('\n').count() > 1
+        && !pr.out.is_empty()
+        && !pr.out.ends_with("\n\n")
+        && !pr.out.ends_with("begin\n")
+        && !pr.out.ends_with("repeat\n")
+        && !pr.out.ends_with(" of\n")
+    {
+        pr.out.push('\n');
+    }
+}
+
+fn comment_text<'a>(pr: &Printer<'a>, c: Span) -> &'a str {
+    pr.src.get(c.start..c.end).unwrap_or("").trim_end()
+}
+
+//Comments before pos, each on a line of its own
+fn leading(pr: &mut Printer, pos: usize, indent: usize) {
+    while let Some(&c) = pr.comments.get(pr.next) {
+        if c.start >= pos {
+            break;
+        }
+        blank_line(pr, c.start);
+        pad(pr, indent);
+        let text = comment_text(pr, c);
+        pr.out.push_str(text);
+        pr.out.push('\n');
+        pr.last = c.end;
+        pr.next += 1;
+    }
+}
+
+//Start a line for a node at pos: comments before it, a blank line if the source has one
+fn line_start(pr: &mut Printer, pos: usize, indent: usize) {
+    leading(pr, pos, indent);
+    blank_line(pr, pos);
+    pad(pr, indent);
+}
+
+//Comments inside a node that ends at end or on the same line after it, at the end of
+//the output line. Those from next on, where the following node starts, are left to it.
+//The caller ends the line, a // comment runs to its end.
+fn trailing(pr: &mut Printer, end: usize, next: usize) {
+    while let Some(&c) = pr.comments.get(pr.next) {
+        let same_line = c.start < end || !pr.src.get(end..c.start).unwrap_or("\n").contains('\n');
+        if !same_line || (c.start >= end && c.start >= next) {
+            break;
+        }
+        pr.out.push(' ');
+        let text = comment_text(pr, c);
+        pr.out.push_str(text);
+        pr.last = pr.last.max(c.end);
+        pr.next += 1;
+    }
+}
+
+//Start of the first symbol at or after pos, comments and white space are skipped
+fn symbol_at(pr: &Printer, mut pos: usize) -> usize {
+    let bytes = pr.src.as_bytes();
+    loop {
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        match pr.comments.iter().find(|c| c.start == pos) {
+            Some(c) => pos = c.end,
+            None => return pos,
+        }
+    }
+}
+
+//Comments before pos inside a statement, kept in its text before the symbol at pos.
+//After a // comment the statement goes on below, two levels deeper than indent.
+fn inline(pr: &mut Printer, pos: usize, indent: usize) -> String {
+    let mut out = String::new();
+    while let Some(&c) = pr.comments.get(pr.next) {
+        if c.start >= pos {
+            break;
+        }
+        let text = comment_text(pr, c);
+        out.push_str(text);
+        if text.starts_with("//") {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2 * INDENT));
+        } else {
+            out.push(' ');
+        }
+        pr.last = c.end;
+        pr.next += 1;
+    }
+    out
+}
+
+fn binop(op: BinOp) -> (&'static str, u8) {
+    match op {
+        BinOp::Add => ("+", 1),
+        BinOp::Sub => ("-", 1),
+        BinOp::Mul => ("*", 2),
+        BinOp::Div => ("/", 2),
+    }
+}
+
+//Relational operator, <=, >= and <> unless the dialect has only the classic ones
+fn relop(pr: &Printer, op: RelOp) -> &'static str {
+    match (op, pr.dialect.classic_relops) {
+        (RelOp::Eq, _) => "=",
+        (RelOp::Ne, false) => "<>",
+        (RelOp::Ne, true) => "#",
+        (RelOp::Lt, _) => "<",
+        (RelOp::Le, false) => "<=",
+        (RelOp::Le, true) => "[",
+        (RelOp::Gt, _) => ">",
+        (RelOp::Ge, false) => ">=",
+        (RelOp::Ge, true) => "]",
+    }
+}
+
+//Binding strength: 1 for expressions, 2 for terms, 3 for factors
+fn precedence(e: &Expr) -> u8 {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Name(_) => 3,
+        ExprKind::Binary(op, _, _) => binop(*op).1,
+        ExprKind::Neg(_) => 1,
+    }
+}
+
+//Expression text with the comments in it, in parentheses if it binds weaker than min
+fn expression(pr: &mut Printer, e: &Expr, min: u8, indent: usize) -> String {
+    let before = inline(pr, e.span.start, indent);
+    let text = match &e.kind {
+        ExprKind::Number(n) => format!("{}{n}", inline(pr, e.span.end, indent)),
+        ExprKind::Name(id) => format!("{}{}", inline(pr, e.span.end, indent), id.name),
+        ExprKind::Neg(x) => {
+            let comments = inline(pr, x.span.start, indent);
+            let space = if comments.is_empty() { "" } else { " " };
+            format!("-{space}{comments}{}", expression(pr, x, 2, indent))
+        }
+        ExprKind::Binary(op, l, r) => {
+            let (sym, prec) = binop(*op);
+            //Operators are left associative: a - (b - c) keeps its parentheses
+            let left = expression(pr, l, prec, indent);
+            let at = symbol_at(pr, l.span.end);
+            let comments = inline(pr, at, indent);
+            let right = expression(pr, r, prec + 1, indent);
+            format!("{left} {comments}{sym} {right}")
+        }
+    };
+    if precedence(e) < min {
+        format!("{before}({text})")
+    } else {
+        format!("{before}{text}")
+    }
+}
+
+//Condition text, in parentheses if it binds weaker than min: 1 for or, 2 for and, 3
+//for comparisons, 4 for not and odd. A comparison after not keeps its parentheses,
+//not a = b would read like Pascal's (not a) = b.
+fn condition(pr: &mut Printer, c: &Cond, min: u8, indent: usize) -> String {
+    let before = inline(pr, c.span.start, indent);
+    let (text, prec) = match &c.kind {
+        CondKind::Odd(e) => (format!("odd {}", expression(pr, e, 1, indent)), 4),
+        CondKind::Compare(op, l, r) => {
+            let left = expression(pr, l, 1, indent);
+            let at = symbol_at(pr, l.span.end);
+            let comments = inline(pr, at, indent);
+            let right = expression(pr, r, 1, indent);
+            (format!("{left} {comments}{} {right}", relop(pr, *op)), 3)
+        }
+        CondKind::Not(x) => (format!("not {}", condition(pr, x, 4, indent)), 4),
+        CondKind::And(l, r) => {
+            let left = condition(pr, l, 2, indent);
+            let at = symbol_at(pr, l.span.end);
+            let comments = inline(pr, at, indent);
+            let right = condition(pr, r, 3, indent);
+            (format!("{left} {comments}and {right}"), 2)
+        }
+        CondKind::Or(l, r) => {
+            let left = condition(pr, l, 1, indent);
+            let at = symbol_at(pr, l.span.end);
+            let comments = inline(pr, at, indent);
+            let right = condition(pr, r, 2, indent);
+            (format!("{left} {comments}or {right}"), 1)
+        }
+    };
+    if prec < min {
+        format!("{before}({text})")
+    } else {
+        format!("{before}{text}")
+    }
+}
+
+//Case label, a value or a range
+fn case_label(pr: &mut Printer, label: &CaseLabel, indent: usize) -> String {
+    let low = expression(pr, &label.low, 1, indent);
+    match &label.high {
+        Some(high) => format!("{low}..{}", expression(pr, high, 1, indent)),
+        None => low,
+    }
+}
+
+//Body of if and while: a begin stays on the header line, other statements go below it
+fn body(pr: &mut Printer, s: &Stmt, indent: usize) {
+    match s.kind {
+        StmtKind::Begin(_) => {
+            pr.out.push(' ');
+            statement(pr, s, indent);
+        }
+        StmtKind::Empty => {}
+        _ => {
+            pr.out.push('\n');
+            leading(pr, s.span.start, indent + INDENT);
+            pad(pr, indent + INDENT);
+            statement(pr, s, indent + INDENT);
+        }
+    }
+}
+
+//Statements one per line, comments before end, the keyword closing them, are printed
+//with them. Empty statements, as after a last semicolon, are left out.
+fn statements(pr: &mut Printer, stmts: &[Stmt], end: usize, indent: usize) {
+    let stmts: Vec<&Stmt> = stmts
+        .iter()
+        .filter(|st| !matches!(st.kind, StmtKind::Empty))
+        .collect();
+    for (i, st) in stmts.iter().enumerate() {
+        line_start(pr, st.span.start, indent);
+        statement(pr, st, indent);
+        if i + 1 < stmts.len() {
+            pr.out.push(';');
+        }
+        let next = stmts.get(i + 1).map_or(end, |n| n.span.start);
+        trailing(pr, st.span.end, next);
+        pr.out.push('\n');
+    }
+    leading(pr, end, indent);
+}
+
+//Statement text from the current position, indent is the column of its first line
+fn statement(pr: &mut Printer, s: &Stmt, indent: usize) {
+    if !matches!(s.kind, StmtKind::Empty) {
+        pr.last = s.span.start;
+    }
+    match &s.kind {
+        StmtKind::Assign(id, e) => {
+            let e = expression(pr, e, 1, indent);
+            pr.out.push_str(&format!("{} := {e}", id.name));
+        }
+        StmtKind::Call(id) => {
+            let comments = inline(pr, id.span.start, indent);
+            pr.out.push_str(&format!("call {comments}{}", id.name));
+        }
+        StmtKind::Read(id) => {
+            let comments = inline(pr, id.span.start, indent);
+            pr.out.push_str(&format!("? {comments}{}", id.name));
+        }
+        StmtKind::Write(e) => {
+            let e = expression(pr, e, 1, indent);
+            pr.out.push_str(&format!("! {e}"));
+        }
+        StmtKind::Begin(stmts) => {
+            pr.out.push_str("begin\n");
+            statements(pr, stmts, s.span.end - "end".len(), indent + INDENT);
+            pad(pr, indent);
+            pr.out.push_str("end");
+        }
+        StmtKind::Repeat(stmts, c) => {
+            pr.out.push_str("repeat\n");
+            //The last statement, empty after a semicolon, ends where until starts
+            let until = stmts
+                .last()
+                .map_or(c.span.start, |st| symbol_at(pr, st.span.end));
+            statements(pr, stmts, until, indent + INDENT);
+            pad(pr, indent);
+            let c = condition(pr, c, 1, indent);
+            pr.out.push_str(&format!("until {c}"));
+        }
+        StmtKind::If(c, then, otherwise) => {
+            let text = condition(pr, c, 1, indent);
+            let comments = inline(pr, symbol_at(pr, c.span.end), indent);
+            pr.out.push_str(&format!("if {text} {comments}then"));
+            pr.last = pr.last.max(c.span.end);
+            body(pr, then, indent);
+            if let Some((keyword, otherwise)) = otherwise {
+                //end else begin, or else on a line of its own
+                if matches!(then.kind, StmtKind::Begin(_)) {
+                    pr.out.push_str(" else");
+                } else {
+                    trailing(pr, then.span.end, keyword.start);
+                    pr.out.push('\n');
+                    leading(pr, keyword.start, indent);
+                    pad(pr, indent);
+                    pr.out.push_str("else");
+                }
+                pr.last = keyword.end;
+                match otherwise.kind {
+                    //else if chains stay flat
+                    StmtKind::If(..) => {
+                        pr.out.push(' ');
+                        statement(pr, otherwise, indent);
+                    }
+                    _ => body(pr, otherwise, indent),
+                }
+            }
+        }
+        StmtKind::While(c, stmt) => {
+            let text = condition(pr, c, 1, indent);
+            let comments = inline(pr, symbol_at(pr, c.span.end), indent);
+            pr.out.push_str(&format!("while {text} {comments}do"));
+            pr.last = pr.last.max(c.span.end);
+            body(pr, stmt, indent);
+        }
+        StmtKind::Case {
+            expr,
+            arms,
+            otherwise,
+        } => {
+            let text = expression(pr, expr, 1, indent);
+            let comments = inline(pr, symbol_at(pr, expr.span.end), indent);
+            pr.out.push_str(&format!("case {text} {comments}of\n"));
+            pr.last = pr.last.max(expr.span.end);
+            let end = s.span.end - "end".len();
+            for (i, arm) in arms.iter().enumerate() {
+                line_start(pr, arm.span.start, indent + INDENT);
+                let arm_indent = indent + INDENT;
+                let labels: Vec<String> = arm
+                    .labels
+                    .iter()
+                    .map(|label| case_label(pr, label, arm_indent))
+                    .collect();
+                pr.out.push_str(&labels.join(", "));
+                pr.out.push(':');
+                if !matches!(arm.body.kind, StmtKind::Empty) {
+                    let comments = inline(pr, arm.body.span.start, arm_indent);
+                    pr.out.push(' ');
+                    pr.out.push_str(&comments);
+                    statement(pr, &arm.body, arm_indent);
+                }
+                if i + 1 < arms.len() {
+                    pr.out.push(';');
+                }
+                pr.last = arm.span.end;
+                let next = match (arms.get(i + 1), otherwise) {
+                    (Some(n), _) => n.span.start,
+                    (None, Some((keyword, _))) => keyword.start,
+                    (None, None) => end,
+                };
+                trailing(pr, arm.span.end, next);
+                pr.out.push('\n');
+            }
+            if let Some((keyword, otherwise)) = otherwise {
+                leading(pr, keyword.start, indent + INDENT);
+                pad(pr, indent);
+                pr.out.push_str("else");
+                pr.last = keyword.end;
+                body(pr, otherwise, indent);
+                trailing(pr, otherwise.span.end, end);
+                pr.out.push('\n');
+            }
+            leading(pr, end, indent + INDENT);
+            pad(pr, indent);
+            pr.out.push_str("end");
+        }
+        StmtKind::For {
+            var,
+            from,
+            down,
+            to,
+            by,
+            body: stmt,
+        } => {
+            let dir = if *down { "downto" } else { "to" };
+            let comments = inline(pr, var.span.start, indent);
+            let from_text = expression(pr, from, 1, indent);
+            let mut text = format!("for {comments}{} := {from_text} ", var.name);
+            text.push_str(&inline(pr, symbol_at(pr, from.span.end), indent));
+            text.push_str(&format!("{dir} {}", expression(pr, to, 1, indent)));
+            if let Some(by) = by {
+                text.push(' ');
+                text.push_str(&inline(pr, symbol_at(pr, to.span.end), indent));
+                text.push_str(&format!("by {}", expression(pr, by, 1, indent)));
+            }
+            let last = by.as_ref().unwrap_or(to).span.end;
+            text.push(' ');
+            text.push_str(&inline(pr, symbol_at(pr, last), indent));
+            pr.out.push_str(&text);
+            pr.out.push_str("do");
+            pr.last = pr.last.max(last);
+            body(pr, stmt, indent);
+        }
+        StmtKind::Empty => {}
+    }
+    if !matches!(s.kind, StmtKind::Empty) {
+        pr.last = s.span.end;
+    }
+}
+
+//Constants one per line, aligned at the `=`
+fn constants(pr: &mut Printer, decls: &[&Decl], indent: usize, after: usize) {
+    let width = decls
+        .iter()
+        .map(|d| match &d.kind {
+            DeclKind::Const(id, _, _) => id.name.len(),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0);
+    for (i, d) in decls.iter().enumerate() {
+        if let DeclKind::Const(id, n, _) = &d.kind {
+            if i == 0 {
+                line_start(pr, d.span.start, indent);
+                pr.out.push_str("const ");
+            } else {
+                line_start(pr, d.span.start, indent + 6);
+            }
+            pr.out.push_str(&format!("{:<width$} = {n}", id.name));
+            pr.out.push(if i + 1 < decls.len() { ',' } else { ';' });
+            pr.last = d.span.end;
+            //A comment further along the line belongs to a later constant on it
+            let next = decls.get(i + 1).map_or(after, |n| n.span.start);
+            trailing(pr, d.span.end, next);
+            pr.out.push('\n');
+        }
+    }
+}
+
+//Variables on one line, wrapped under the first name when too long
+fn variables(pr: &mut Printer, decls: &[&Decl], indent: usize, after: usize) {
+    line_start(pr, decls[0].span.start, indent);
+    pr.out.push_str("var ");
+    let mut col = indent + 4;
+    for (i, d) in decls.iter().enumerate() {
+        if let DeclKind::Var(id) = &d.kind {
+            if i > 0 {
+                if col + 2 + id.name.len() + 1 > WIDTH {
+                    pr.out.push_str(",\n");
+                    pad(pr, indent + 4);
+                    col = indent + 4;
+                } else {
+                    pr.out.push_str(", ");
+                    col += 2;
+                }
+            }
+            pr.out.push_str(&id.name);
+            col += id.name.len();
+            pr.last = d.span.end;
+        }
+    }
+    pr.out.push(';');
+    trailing(pr, pr.last, after);
+    pr.out.push('\n');
+}
+
+//Block at indent, procedures declared in it go one level deeper except in the main program
+fn block(pr: &mut Printer, b: &Block, indent: usize, main: bool) {
+    let proc_indent = if main { indent } else { indent + INDENT };
+    let mut i = 0;
+    while i < b.decls.len() {
+        let d = &b.decls[i];
+        if i > 0 && main && matches!(d.kind, DeclKind::Procedure(..)) {
+            separate(pr);
+        }
+        match &d.kind {
+            DeclKind::Procedure(id, body) => {
+                line_start(pr, d.span.start, proc_indent);
+                pr.out.push_str(&format!("procedure {};", id.name));
+                pr.last = id.span.end;
+                trailing(pr, id.span.end, body.span.start);
+                pr.out.push('\n');
+                block(pr, body, proc_indent, false);
+                pr.out.push(';');
+                pr.last = d.span.end;
+                trailing(pr, d.span.end, following(b, i + 1));
+                pr.out.push('\n');
+                i += 1;
+            }
+            kind => {
+                //A run of declarations of one kind
+                let run: Vec<&Decl> = b.decls[i..]
+                    .iter()
+                    .take_while(|d| std::mem::discriminant(&d.kind) == std::mem::discriminant(kind))
+                    .collect();
+                i += run.len();
+                match kind {
+                    DeclKind::Const(..) => constants(pr, &run, indent, following(b, i)),
+                    _ => variables(pr, &run, indent, following(b, i)),
+                }
+            }
+        }
+    }
+    if main && !b.decls.is_empty() {
+        separate(pr);
+    }
+    line_start(pr, b.body.span.start, indent);
+    statement(pr, &b.body, indent);
+}
+
+//Start of the declaration at index i of a block, or of its body after the last one
+fn following(b: &Block, i: usize) -> usize {
+    b.decls.get(i).map_or(b.body.span.start, |d| d.span.start)
+}
+
+//Blank line between the procedures and the body of the main program
+fn separate(pr: &mut Printer) {
+    if !pr.out.ends_with("\n\n") {
+        pr.out.push('\n');
+    }
+}
+
+//Program text in the canonical layout, src is the source it was parsed from
+pub fn format_program(prog: &Program, src: &str, dialect: Dialect) -> String {
+    let mut pr = Printer {
+        src,
+        comments: &prog.comments,
+        dialect,
+        next: 0,
+        last: 0,
+        out: String::new(),
+    };
+    block(&mut pr, &prog.block, 0, true);
+    pr.out.push('.');
+    pr.last = prog.span.end;
+    trailing(&mut pr, prog.span.end, usize::MAX);
+    pr.out.push('\n');
+    leading(&mut pr, usize::MAX, 0);
+    pr.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Source;
+
+    //Sources in any layout, with comments in the places the printer moves them
+    const SOURCES: &[&str] = &[
+        "const   max=10,m=2;\nvar x,y;\nprocedure p;\nbegin x:=x+1 end;\nbegin\n  x := (max - m) * 2; if x # 0 then begin ? y; while y [ max do y := y + 1 end;\n\n\n  call p; ! x end.\n",
+        "var x, { a } y; (* header *)\nbegin x := 1 + { two } 2; // line\n  if x > 0 { pos } then\n y := 1 { done }\nend. { trailer }\n// final\n",
+        "var a;\nbegin if a <= 1 then ! 1; if a >= 1 then ! 2; if a <> 1 then ! 3; if a != 1 then ! 4; if a # 1 then ! 5 end.\n",
+        "var y;\nbegin\n  if 1 > 0 then y := 1 else if y > 0 then y := 2 else begin ! 1 end\nend.\n",
+        "var n;\nbegin n := 0; repeat n := n + 1; ! n until n >= 3 end.\n",
+        "var i;\nbegin for i := 10 downto 1 by 3 do ! i; for i := 1 to 2 do begin ! i end end.\n",
+        "var i;\nbegin for i := 0 to 9 do case i of 1, 2: ! i; 3..7: begin ! 0 end; 8: ! 8; else ! 9 end end.\n",
+        "var n;\nbegin n := 5; while not (n = 0) and (n > 1 or (n) * 2 > 7) do n := n - 1; if not odd n or n = 1 then ! n end.\n",
+        "const   max=10, m = 2; // trailing\nvar x, { a } y;\nbegin x := max; y := m // c\nend.\n",
+        "var y;\nbegin\n  if 1 > 0 then y := 1\n  { before else }\n  else { neg } y := 2;\n  if y > 0 then begin ! 1 end else // after\n ! 2\nend.\n",
+        "var x;\nbegin\n  case x of\n    1: ! 1\n    { before else }\n  else\n    { other }\n    ! 2\n  end\nend.\n",
+        "var a;\nbegin if a = 1 then else ! 2; case a of 1: ! 1; 2: else ! 3 end end.\n",
+    ];
+
+    //Sources in the canonical layout already, the printer gives them back as they are
+    const FORMATTED: &[&str] = &[
+        "var n;\n\nbegin\n  n := 0;\n  while n < 10 do begin\n    n := n + 1;\n    ! n\n  end\nend.\n",
+        "var x; // counter\n\nbegin\n  { start }\n  x := 1 { one }\nend.\n",
+        "var a;\n\nbegin\n  if a <= 1 then\n    ! 1\nend.\n",
+        "begin\n  if 1 > 0 then\n    ! 1\n  else\n    ! 2\nend.\n",
+        "var n;\n\nbegin\n  repeat\n    n := n + 1\n  until n >= 3\nend.\n",
+        "var i;\n\nbegin\n  for i := 1 to 3 do\n    ! i\nend.\n",
+        "var i;\n\nbegin\n  case i of\n    1, 2: ! i;\n    3..7: ! 0\n  else\n    ! 9\n  end\nend.\n",
+        "var n;\n\nbegin\n  while not (n = 0) and (n > 1 or n * 2 > 7) do\n    n := n - 1\nend.\n",
+        "const max = 10,\n      m   = 2; // trailing\nvar x;\n\nbegin\n  x := max; // first\n  ! x\nend.\n",
+        "begin\n  if 1 > 0 then\n    ! 1\n  else\n    { neg }\n    ! 2\nend.\n",
+        "var x;\n\nbegin\n  case x of\n    1: ! 1\n    { before else }\n  else\n    { other }\n    ! 2\n  end\nend.\n",
+        "var a;\n\nbegin\n  if a = 1 then\n  else\n    ! 2;\n  case a of\n    1:\n  else\n    ! 3\n  end\nend.\n",
+    ];
+
+    fn sources() -> Vec<String> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+        let mut out: Vec<String> = SOURCES
+            .iter()
+            .chain(FORMATTED)
+            .map(|s| s.to_string())
+            .collect();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "pl0") {
+                out.push(std::fs::read_to_string(path).unwrap());
+            }
+        }
+        out
+    }
+
+    fn fmt(text: &str) -> String {
+        let tree = crate::parse_source(&Source::new(text)).unwrap();
+        format_program(&tree, text, Dialect::default())
+    }
+
+    fn comments(text: &str) -> Vec<String> {
+        let tree = crate::parse_source(&Source::new(text)).unwrap();
+        let comments = tree.comments.iter();
+        comments
+            .map(|c| text[c.start..c.end].trim_end().to_owned())
+            .collect()
+    }
+
+    fn code(text: &str) -> String {
+        let prog = crate::compile(text).unwrap();
+        format!("{:?}", prog.code)
+    }
+
+    #[test]
+    fn idempotent() {
+        for src in sources() {
+            let once = fmt(&src);
+            assert_eq!(fmt(&once), once, "formatting again changed:\n{once}");
+        }
+    }
+
+    #[test]
+    fn formatted_unchanged() {
+        for src in FORMATTED {
+            assert_eq!(fmt(src), *src);
+        }
+    }
+
+    #[test]
+    fn keeps_comments() {
+        for src in sources() {
+            assert_eq!(comments(&fmt(&src)), comments(&src), "in:\n{src}");
+        }
+    }
+
+    #[test]
+    fn same_code() {
+        for src in sources() {
+            assert_eq!(code(&fmt(&src)), code(&src), "in:\n{src}");
+        }
+    }
+
+    #[test]
+    fn comments_inside_statements() {
+        let cases = [
+            (
+                "var a;\nbegin\n  a := 1 +\n  { mid }\n  2\nend.\n",
+                "var a;\n\nbegin\n  a := 1 + { mid } 2\nend.\n",
+            ),
+            (
+                "var a;\nbegin a := - { neg } a + // mid\n 2 end.\n",
+                "var a;\n\nbegin\n  a := - { neg } a + // mid\n      2\nend.\n",
+            ),
+            (
+                "var a;\nbegin case a { sel } of 1: ! 1 end end.\n",
+                "var a;\n\nbegin\n  case a { sel } of\n    1: ! 1\n  end\nend.\n",
+            ),
+            (
+                "var a;\nbegin case a of 1, { two } 2: { one } ! 1 end end.\n",
+                "var a;\n\nbegin\n  case a of\n    1, { two } 2: { one } ! 1\n  end\nend.\n",
+            ),
+            (
+                "var i;\nbegin for i := 1 { from } to 3 by 1 { step } do ! i end.\n",
+                "var i;\n\nbegin\n  for i := 1 { from } to 3 by 1 { step } do\n    ! i\nend.\n",
+            ),
+            (
+                "var a;\nbegin if a > 1 // low\n and a < 9 { high } then ! a end.\n",
+                "var a;\n\nbegin\n  if a > 1 // low\n      and a < 9 { high } then\n    ! a\nend.\n",
+            ),
+            (
+                "var a;\nbegin repeat a := a - 1; { last } until { u } a < 0 end.\n",
+                "var a;\n\nbegin\n  repeat\n    a := a - 1 { last }\n  until { u } a < 0\nend.\n",
+            ),
+            (
+                "var a;\nprocedure p;\nbegin a := 1 end; { after p }\nbegin call { p } p end.\n",
+                "var a;\n\nprocedure p;\nbegin\n  a := 1\nend; { after p }\n\nbegin\n  call { p } p\nend.\n",
+            ),
+            (
+                "var a;\nbegin case a of 1: ! 1 end; { after case }\n a := 2 end.\n",
+                "var a;\n\nbegin\n  case a of\n    1: ! 1\n  end; { after case }\n  a := 2\nend.\n",
+            ),
+        ];
+        for (src, formatted) in cases {
+            assert_eq!(fmt(src), formatted, "in:\n{src}");
+            assert_eq!(fmt(formatted), formatted);
+        }
+    }
+}
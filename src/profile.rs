@@ -0,0 +1,190 @@
+//Instruction counts of a program run and a report of where the time went: procedures,
+//loops (backward jumps), source lines and single instructions.
+
+use crate::defs::{Fct, Program};
+use crate::interpreter::{step, InterpreterConfig, Machine, RuntimeError};
+use crate::io::Io;
+use crate::listing::instr_listing;
+use std::cmp::Reverse;
+
+const HOT_ITEMS: usize = 10; //Lines and instructions shown in the report
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub counts: Vec<u64>, //Executions of every instruction address
+    pub taken: Vec<u64>,  //Times every jpc jumped, that is its condition was false
+    pub total: u64,       //Instructions executed
+}
+
+//Interpret the program counting executions, counts are kept even if the program fails
+pub fn interpret_profiled(
+    prog: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+    profile: &mut Profile,
+) -> Result<(), RuntimeError> {
+    profile.counts = vec![0; prog.code.len()];
+    profile.taken = vec![0; prog.code.len()];
+    profile.total = 0;
+    let mut m = Machine::new(prog, config)?;
+    while !m.halted {
+        let pc = m.p as usize;
+        //A jpc jumps when the condition it pops is false, even to the next address
+        let jumps = matches!(prog.code[pc].fct, Fct::Jpc) && m.s.get(m.t) == Some(&0);
+        step(&mut m, io)?;
+        profile.counts[pc] += 1;
+        profile.total += 1;
+        if jumps {
+            profile.taken[pc] += 1;
+        }
+    }
+    Ok(())
+}
+
+//Procedure name and entry address. From the parser's scopes if known, otherwise
+//the targets of cal instructions and the main program body.
+fn procedures(prog: &Program) -> Vec<(String, i32)> {
+    if !prog.scopes.is_empty() {
+        return prog
+            .scopes
+            .iter()
+            .map(|sc| match sc.name.is_empty() {
+                true => ("<main>".to_owned(), sc.entry),
+                false => (sc.name.clone(), sc.entry),
+            })
+            .collect();
+    }
+    let main = match prog.code.get(prog.entry as usize) {
+        Some(instr) if matches!(instr.fct, Fct::Jmp) => instr.adr,
+        _ => prog.entry,
+    };
+    let mut procs = vec![("<main>".to_owned(), main)];
+    for instr in &prog.code {
+        if matches!(instr.fct, Fct::Cal) && !procs.iter().any(|(_, adr)| *adr == instr.adr) {
+            procs.push((format!("proc@{}", instr.adr), instr.adr));
+        }
+    }
+    procs
+}
+
+//Procedure an address belongs to: the innermost scope, or without scopes the nearest
+//entry before it (a body follows the procedures nested in it)
+fn owner(prog: &Program, procs: &[(String, i32)], adr: i32) -> Option<usize> {
+    if !prog.scopes.is_empty() {
+        return prog.scope_at(adr);
+    }
+    procs
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, entry))| *entry <= adr)
+        .max_by_key(|(_, (_, entry))| *entry)
+        .map(|(i, _)| i)
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        n as f64 * 100.0 / total as f64
+    }
+}
+
+//Items by count, highest first, ties in address order
+fn hottest<T>(mut items: Vec<(T, u64)>) -> Vec<(T, u64)> {
+    items.sort_by_key(|item| Reverse(item.1));
+    items
+}
+
+fn lines_of(prog: &Program, from: usize, to: usize) -> String {
+    match (prog.lines.get(from), prog.lines.get(to)) {
+        (Some(a), Some(b)) if a == b => format!("line {a}"),
+        (Some(a), Some(b)) => format!("lines {}-{}", a.min(b), a.max(b)),
+        _ => String::new(),
+    }
+}
+
+pub fn report(prog: &Program, source: &str, profile: &Profile) -> String {
+    let total = profile.total;
+    let count = |adr: usize| profile.counts.get(adr).copied().unwrap_or(0);
+    let mut out = format!("Profile: {total} instructions executed\n");
+
+    //Procedures: calls are executions of the entry, instructions are those of the body
+    let procs = procedures(prog);
+    let mut own = vec![0; procs.len()];
+    for adr in 0..prog.code.len() {
+        if let Some(i) = owner(prog, &procs, adr as i32) {
+            own[i] += count(adr);
+        }
+    }
+    out.push_str("\nProcedures                      calls  instructions      %\n");
+    let items: Vec<(usize, u64)> = own.iter().copied().enumerate().collect();
+    for (i, n) in hottest(items) {
+        let (name, entry) = &procs[i];
+        out.push_str(&format!(
+            "  {name:<28}{:>7}{n:>14}{:>7.1}\n",
+            count(*entry as usize),
+            percent(n, total)
+        ));
+    }
+
+    //Loops: a jump back to an earlier address closes a loop body, a jmp for while and
+    //a jpc for repeat, which jumps back when its condition is false
+    let mut loops = Vec::new();
+    for (adr, instr) in prog.code.iter().enumerate() {
+        let backward = instr.adr >= 0 && instr.adr as usize <= adr;
+        let iterations = match instr.fct {
+            Fct::Jmp => count(adr),
+            Fct::Jpc => profile.taken.get(adr).copied().unwrap_or(0),
+            _ => continue,
+        };
+        if backward {
+            let from = instr.adr as usize;
+            let body: u64 = (from..=adr).map(count).sum();
+            loops.push(((from, adr, iterations), body));
+        }
+    }
+    if !loops.is_empty() {
+        out.push_str("\nLoops                           iterations  instructions      %\n");
+        for ((from, to, iterations), n) in hottest(loops) {
+            let place = format!("{} (addresses {from}-{to})", lines_of(prog, from, to));
+            out.push_str(&format!(
+                "  {:<28}{iterations:>12}{n:>14}{:>7.1}\n",
+                place.trim_start(),
+                percent(n, total)
+            ));
+        }
+    }
+
+    //Source lines
+    if prog.lines.len() == prog.code.len() {
+        let mut per_line: Vec<(i32, u64)> = Vec::new();
+        for (adr, line) in prog.lines.iter().enumerate() {
+            match per_line.iter_mut().find(|(l, _)| l == line) {
+                Some((_, n)) => *n += count(adr),
+                None => per_line.push((*line, count(adr))),
+            }
+        }
+        per_line.sort_by_key(|(line, _)| *line);
+        out.push_str("\nLines                     instructions      %\n");
+        let text: Vec<&str> = source.lines().collect();
+        for (line, n) in hottest(per_line).into_iter().take(HOT_ITEMS) {
+            let src = text.get((line - 1) as usize).map_or("", |t| t.trim());
+            out.push_str(&format!(
+                "  {line:>5}{n:>25}{:>7.1}   {src}\n",
+                percent(n, total)
+            ));
+        }
+    }
+
+    //Single instructions
+    out.push_str("\nInstructions                 count      %\n");
+    let items: Vec<(usize, u64)> = (0..prog.code.len()).map(|adr| (adr, count(adr))).collect();
+    for (adr, n) in hottest(items).into_iter().take(HOT_ITEMS) {
+        out.push_str(&format!(
+            "  {}{n:>12}{:>7.1}\n",
+            instr_listing(adr, &prog.code[adr]),
+            percent(n, total)
+        ));
+    }
+    out
+}
@@ -1,94 +1,179 @@
-use crate::defs::EX_NOINPUT;
-use crate::scan_error;
-use crate::token::Token;
+use crate::defs::{Dialect, ERR_MSGS};
+use crate::diagnostic::Diagnostic;
+use crate::token::{Span, Token};
 use std::collections::HashMap;
-use std::fs::read_to_string;
 
 #[derive(Debug, PartialEq)]
 
 pub struct Scanner {
+    pub name: Option<String>, //Source name for diagnostics
     source: Vec<char>,
+    offsets: Vec<usize>, //Byte offset of every char in source
     pub line: i32,
     start: i32,
     current: i32,
+    line_start: i32, //Index of first char of current line
+    start_line: i32, //Line and column where current lexeme starts
+    start_col: i32,
     keywords: HashMap<String, Token>,
+    dialect: Dialect,
+    pub comments: Vec<Span>, //Comments found while scanning, for the formatter
+    pub errors: Vec<Diagnostic>, //Errors found while scanning
 }
 
 impl Scanner {
-    pub fn new(src: &str) -> Scanner {
-        let source = match read_to_string(src) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Could not read source file {src}, error {e}");
-                std::process::exit(EX_NOINPUT);
-            }
-        };
-
+    pub fn new(source: &str, name: Option<&str>, dialect: Dialect) -> Scanner {
         let mut kw: HashMap<String, Token> = HashMap::new();
+        kw.insert("and".to_owned(), Token::AndSym);
         kw.insert("begin".to_owned(), Token::BeginSym);
+        kw.insert("by".to_owned(), Token::BySym);
         kw.insert("call".to_owned(), Token::CallSym);
+        kw.insert("case".to_owned(), Token::CaseSym);
         kw.insert("const".to_owned(), Token::ConstSym);
         kw.insert("do".to_owned(), Token::DoSym);
+        kw.insert("downto".to_owned(), Token::DowntoSym);
+        kw.insert("else".to_owned(), Token::ElseSym);
         kw.insert("end".to_owned(), Token::EndSym);
+        kw.insert("for".to_owned(), Token::ForSym);
         kw.insert("if".to_owned(), Token::IfSym);
+        kw.insert("not".to_owned(), Token::NotSym);
         kw.insert("odd".to_owned(), Token::OddSym);
+        kw.insert("of".to_owned(), Token::OfSym);
+        kw.insert("or".to_owned(), Token::OrSym);
         kw.insert("procedure".to_owned(), Token::ProcSym);
+        kw.insert("repeat".to_owned(), Token::RepeatSym);
         kw.insert("then".to_owned(), Token::ThenSym);
+        kw.insert("to".to_owned(), Token::ToSym);
+        kw.insert("until".to_owned(), Token::UntilSym);
         kw.insert("var".to_owned(), Token::VarSym);
         kw.insert("while".to_owned(), Token::WhileSym);
 
+        let mut offsets: Vec<usize> = source.char_indices().map(|(i, _)| i).collect();
+        offsets.push(source.len());
+
         Self {
+            name: name.map(|n| n.to_owned()),
             source: source.chars().collect(),
+            offsets,
             line: 1,
             start: 0,
             current: 0,
+            line_start: 0,
+            start_line: 1,
+            start_col: 1,
             keywords: kw,
+            dialect,
+            comments: Vec::new(),
+            errors: Vec::new(),
         }
     }
 }
 
-pub fn next_sym(s: &mut Scanner) -> Token {
-    if !is_at_end(s) {
-        //We are at the beginning of next lexeme
-        s.start = s.current;
-        return scan_token(s);
+pub fn next_sym(s: &mut Scanner) -> (Token, Span) {
+    //We are at the beginning of next lexeme
+    s.start = s.current;
+    s.start_line = s.line;
+    s.start_col = s.start - s.line_start + 1;
+    let tok = if !is_at_end(s) {
+        scan_token(s)
     } else {
-        return Token::Eof;
+        Token::Eof
+    };
+    (tok, lexeme_span(s))
+}
+
+fn lexeme_span(s: &Scanner) -> Span {
+    Span {
+        line: s.start_line,
+        col: s.start_col,
+        start: s.offsets[s.start as usize],
+        end: s.offsets[s.current as usize],
     }
 }
 
+fn scan_error(s: &mut Scanner, n: usize) {
+    let d = Diagnostic::new(s.name.as_deref(), Some(lexeme_span(s)), n, ERR_MSGS[n]);
+    s.errors.push(d);
+}
+
 fn scan_token(s: &mut Scanner) -> Token {
     let ret: Token;
     let c = advance(s);
     match c {
-        '(' => ret = Token::LParen,
+        '{' => ret = block_comment(s, '}'),
+        '(' => {
+            if _match(s, '*') {
+                s.current += 1;
+                ret = block_comment(s, ')');
+            } else {
+                ret = Token::LParen;
+            }
+        }
         ')' => ret = Token::RParen,
         ',' => ret = Token::Comma,
-        '.' => ret = Token::Period,
+        '.' => {
+            if _match(s, '.') {
+                s.current += 1;
+                ret = Token::DotDot;
+            } else {
+                ret = Token::Period;
+            }
+        }
         '-' => ret = Token::Minus,
         '+' => ret = Token::Plus,
         '*' => ret = Token::Times,
         '#' => ret = Token::NotEqual,
         '=' => ret = Token::Equal,
         ';' => ret = Token::Semicolon,
-        '/' => ret = Token::Slash,
+        '/' => {
+            if _match(s, '/') {
+                ret = line_comment(s);
+            } else {
+                ret = Token::Slash;
+            }
+        }
         ':' => {
             if _match(s, '=') {
                 s.current += 1;
                 ret = Token::Becomes;
             } else {
-                ret = Token::BecomesErr;
+                ret = Token::Colon;
+            }
+        }
+        '<' => {
+            if !s.dialect.classic_relops && _match(s, '=') {
+                s.current += 1;
+                ret = Token::LessEqual;
+            } else if !s.dialect.classic_relops && _match(s, '>') {
+                s.current += 1;
+                ret = Token::NotEqual;
+            } else {
+                ret = Token::Less;
+            }
+        }
+        '>' => {
+            if !s.dialect.classic_relops && _match(s, '=') {
+                s.current += 1;
+                ret = Token::GreaterEqual;
+            } else {
+                ret = Token::Greater;
             }
         }
-        '<' => ret = Token::Less,
-        '>' => ret = Token::Greater,
         '[' => ret = Token::LessEqual,
         ']' => ret = Token::GreaterEqual,
-        '!' => ret = Token::WriteSym,
+        '!' => {
+            if !s.dialect.classic_relops && _match(s, '=') {
+                s.current += 1;
+                ret = Token::NotEqual;
+            } else {
+                ret = Token::WriteSym;
+            }
+        }
         '?' => ret = Token::ReadSym,
         ' ' | '\r' | '\t' => ret = Token::WhiteSpace(c),
         '\n' => {
             s.line += 1;
+            s.line_start = s.current;
             ret = Token::WhiteSpace(c);
         }
         _ => {
@@ -100,13 +185,67 @@ fn scan_token(s: &mut Scanner) -> Token {
                 ret = si;
             } else {
                 ret = Token::WhiteSpace(' ');
-                scan_error(s.line, "unexpected character");
+                scan_error(s, 33);
             }
         }
     }
     ret
 }
 
+//Comment after its opener, up to the closer: '}' for { and ')' for (*. Comments are
+//whitespace to the parser, their spans are kept for the formatter.
+fn block_comment(s: &mut Scanner, closer: char) -> Token {
+    let mut open = vec![closer]; //Closers of the comments not yet closed, innermost last
+    while let Some(&innermost) = open.last() {
+        if is_at_end(s) {
+            //Only the opener is marked, the rest of the file is the comment
+            let opener = if closer == '}' { 1 } else { 2 };
+            let d = Diagnostic::new(
+                s.name.as_deref(),
+                Some(Span {
+                    end: s.offsets[(s.start + opener) as usize],
+                    ..lexeme_span(s)
+                }),
+                35,
+                ERR_MSGS[35],
+            );
+            s.errors.push(d);
+            return Token::WhiteSpace(' ');
+        }
+        let c = advance(s);
+        match c {
+            '\n' => {
+                s.line += 1;
+                s.line_start = s.current;
+            }
+            '}' if innermost == '}' => {
+                open.pop();
+            }
+            '*' if innermost == ')' && _match(s, ')') => {
+                s.current += 1;
+                open.pop();
+            }
+            '{' if s.dialect.nested_comments => open.push('}'),
+            '(' if s.dialect.nested_comments && _match(s, '*') => {
+                s.current += 1;
+                open.push(')');
+            }
+            _ => {}
+        }
+    }
+    s.comments.push(lexeme_span(s));
+    Token::WhiteSpace(' ')
+}
+
+//Comment from // to the end of the line, the newline is left for scan_token
+fn line_comment(s: &mut Scanner) -> Token {
+    while !is_at_end(s) && peek(s) != '\n' {
+        advance(s);
+    }
+    s.comments.push(lexeme_span(s));
+    Token::WhiteSpace(' ')
+}
+
 fn peek(s: &Scanner) -> char {
     if is_at_end(s) {
         return '\0';
@@ -134,22 +273,18 @@ fn identifier(s: &mut Scanner) -> Token {
         v.push(s.source[i as usize]);
     }
     let st: String = v.iter().collect();
-    let mut _type = s.keywords.get(&st);
-    let tt: Token;
-    if _type.is_none() {
-        tt = Token::Identifier(st);
-    } else {
-        tt = _type.unwrap().clone();
+    match s.keywords.get(&st) {
+        Some(kw) => kw.clone(),
+        None => Token::Identifier(st),
     }
-    tt
 }
 
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
 fn is_alpha(c: char) -> bool {
-    c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z'
+    c.is_ascii_alphabetic()
 }
 
 fn is_alphanumeric(c: char) -> bool {
@@ -166,7 +301,8 @@ fn number(s: &mut Scanner) -> Token {
         v.push(s.source[i as usize]);
     }
     let st: String = v.iter().collect();
-    let n: i32 = str::parse::<i32>(&st).unwrap();
+    //Too large for i32: parser reports it as too large a number anyway
+    let n: i32 = str::parse::<i32>(&st).unwrap_or(i32::MAX);
     Token::Number(n)
 }
 
@@ -0,0 +1,41 @@
+use crate::defs::Dialect;
+use std::fs::read_to_string;
+use std::io::{self, Read};
+
+//Program text together with the name used in diagnostics and the dialect it is written in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub name: Option<String>, //File or virtual name, None for anonymous snippets
+    pub text: String,         //Program text
+    pub dialect: Dialect,     //Language options for the scanner
+}
+
+impl Source {
+    pub fn new(text: &str) -> Self {
+        Source {
+            name: None,
+            text: text.to_owned(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    pub fn named(name: &str, text: &str) -> Self {
+        Source {
+            name: Some(name.to_owned()),
+            text: text.to_owned(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    //Read source file, "-" reads standard input
+    pub fn load(path: &str) -> io::Result<Self> {
+        if path == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(Source::named("<stdin>", &text))
+        } else {
+            let text = read_to_string(path)?;
+            Ok(Source::named(path, &text))
+        }
+    }
+}
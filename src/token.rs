@@ -1,4 +1,14 @@
 use std::fmt;
+use std::ops::BitOr;
+
+//Location of a token in the source, file name is kept by the scanner
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Span {
+    pub line: i32,    //1-based line
+    pub col: i32,     //1-based column in characters
+    pub start: usize, //Byte offset of first character
+    pub end: usize,   //Byte offset past last character
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
@@ -12,73 +22,169 @@ pub enum Token {
     Slash,              // /
 
     Equal,        //=
-    NotEqual,     //# (yes this is due to original restriction of one char)
+    NotEqual,     //# (yes this is due to original restriction of one char), <> or !=
     Less,         // <
-    LessEqual,    // [ (another one character odd thing)
+    LessEqual,    // [ (another one character odd thing) or <=
     Greater,      // >
-    GreaterEqual, // ] (yet another one character odd thing)
-
-    LParen,     // (
-    RParen,     // )
-    Comma,      // ;
-    Semicolon,  // ;
-    Period,     // .
-    Becomes,    // := (a traditional Pascal assignment)
-    BecomesErr, //Becomes started correctly with ':' but did not end in '='. Unused in parser.
-
-    BeginSym, // begin
-    EndSym,   // end
-    IfSym,    // if
-    ThenSym,  // then
-    WhileSym, // while
-    DoSym,    // do
-    CallSym,  // call (for easier parsing versus Identifers (look EBNF))
-    ConstSym, // const
-    VarSym,   // var
-    ProcSym,  // procedure
-    OddSym,   //Inbuilt function odd
+    GreaterEqual, // ] (yet another one character odd thing) or >=
+
+    LParen,    // (
+    RParen,    // )
+    Comma,     // ;
+    Semicolon, // ;
+    Period,    // .
+    Becomes,   // := (a traditional Pascal assignment)
+    Colon,     // : of case labels, or a := missing its =
+    DotDot,    // .. of case label ranges
+
+    BeginSym,  // begin
+    EndSym,    // end
+    IfSym,     // if
+    ThenSym,   // then
+    ElseSym,   // else
+    RepeatSym, // repeat
+    UntilSym,  // until
+    ForSym,    // for
+    ToSym,     // to
+    DowntoSym, // downto
+    BySym,     // by
+    CaseSym,   // case
+    OfSym,     // of
+    AndSym,    // and
+    OrSym,     // or
+    NotSym,    // not
+    WhileSym,  // while
+    DoSym,     // do
+    CallSym,   // call (for easier parsing versus Identifers (look EBNF))
+    ConstSym,  // const
+    VarSym,    // var
+    ProcSym,   // procedure
+    OddSym,    //Inbuilt function odd
 
     WriteSym, // !
     ReadSym,  // ?
 }
 
 pub fn sym_relational(sym: &Token) -> bool {
-    match sym {
+    matches!(
+        sym,
         Token::Equal
-        | Token::NotEqual
-        | Token::Less
-        | Token::GreaterEqual
-        | Token::Greater
-        | Token::LessEqual => true,
-        _ => false,
-    }
+            | Token::NotEqual
+            | Token::Less
+            | Token::GreaterEqual
+            | Token::Greater
+            | Token::LessEqual
+    )
 }
 
-pub fn sym_in_facbegsys(sym: &Token) -> bool {
-    match sym {
-        Token::Identifier(_) | Token::Number(_) | Token::LParen => true,
-        _ => false,
+//Set of symbols, a stand-in for Pascal's "set of symbol" used in error recovery
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct SymSet(u64);
+
+impl SymSet {
+    pub const EMPTY: SymSet = SymSet(0);
+
+    pub fn new(syms: &[Token]) -> SymSet {
+        SymSet(syms.iter().fold(0, |set, sym| set | sym_bit(sym)))
     }
-}
 
-pub fn sym_in_declbegsys(sym: &Token) -> bool {
-    match sym {
-        Token::ConstSym | Token::VarSym | Token::ProcSym => true,
-        _ => false,
+    pub fn contains(&self, sym: &Token) -> bool {
+        self.0 & sym_bit(sym) != 0
     }
 }
 
-pub fn sym_in_statbegsys_plus_semicolon(sym: &Token) -> bool {
-    match sym {
-        Token::Identifier(_) | Token::Number(_) | Token::LParen | Token::Semicolon => true,
-        _ => false,
+impl BitOr for SymSet {
+    type Output = SymSet;
+
+    fn bitor(self, other: SymSet) -> SymSet {
+        SymSet(self.0 | other.0)
     }
 }
 
+//One bit per kind of symbol, the value of Identifier and Number does not matter
+fn sym_bit(sym: &Token) -> u64 {
+    let ord = match sym {
+        Token::Eof => 0,
+        Token::WhiteSpace(_) => 1,
+        Token::Identifier(_) => 2,
+        Token::Number(_) => 3,
+        Token::Plus => 4,
+        Token::Minus => 5,
+        Token::Times => 6,
+        Token::Slash => 7,
+        Token::Equal => 8,
+        Token::NotEqual => 9,
+        Token::Less => 10,
+        Token::LessEqual => 11,
+        Token::Greater => 12,
+        Token::GreaterEqual => 13,
+        Token::LParen => 14,
+        Token::RParen => 15,
+        Token::Comma => 16,
+        Token::Semicolon => 17,
+        Token::Period => 18,
+        Token::Becomes => 19,
+        Token::Colon => 20,
+        Token::BeginSym => 21,
+        Token::EndSym => 22,
+        Token::IfSym => 23,
+        Token::ThenSym => 24,
+        Token::WhileSym => 25,
+        Token::DoSym => 26,
+        Token::CallSym => 27,
+        Token::ConstSym => 28,
+        Token::VarSym => 29,
+        Token::ProcSym => 30,
+        Token::OddSym => 31,
+        Token::WriteSym => 32,
+        Token::ReadSym => 33,
+        Token::ElseSym => 34,
+        Token::RepeatSym => 35,
+        Token::UntilSym => 36,
+        Token::ForSym => 37,
+        Token::ToSym => 38,
+        Token::DowntoSym => 39,
+        Token::BySym => 40,
+        Token::CaseSym => 41,
+        Token::OfSym => 42,
+        Token::DotDot => 43,
+        Token::AndSym => 44,
+        Token::OrSym => 45,
+        Token::NotSym => 46,
+    };
+    1 << ord
+}
+
+pub fn declbegsys() -> SymSet {
+    SymSet::new(&[Token::ConstSym, Token::VarSym, Token::ProcSym])
+}
+
+pub fn statbegsys() -> SymSet {
+    SymSet::new(&[
+        Token::BeginSym,
+        Token::CallSym,
+        Token::IfSym,
+        Token::WhileSym,
+        Token::RepeatSym,
+        Token::ForSym,
+        Token::CaseSym,
+        Token::WriteSym,
+        Token::ReadSym,
+    ])
+}
+
+pub fn facbegsys() -> SymSet {
+    SymSet::new(&[
+        Token::Identifier(String::new()),
+        Token::Number(0),
+        Token::LParen,
+    ])
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Token::Eof | Token::BecomesErr => write!(f, ""),
+            Token::Eof => write!(f, ""),
             Token::WhiteSpace(c) => write!(f, "{}", c),
             Token::Identifier(s) => write!(f, "{}", s),
             Token::Number(n) => write!(f, "{}", n),
@@ -98,10 +204,24 @@ impl fmt::Display for Token {
             Token::Semicolon => write!(f, ";"),
             Token::Period => write!(f, "."),
             Token::Becomes => write!(f, ":="),
+            Token::Colon => write!(f, ":"),
+            Token::DotDot => write!(f, ".."),
             Token::BeginSym => write!(f, "begin"),
             Token::EndSym => write!(f, "end"),
             Token::IfSym => write!(f, "if"),
             Token::ThenSym => write!(f, "then"),
+            Token::ElseSym => write!(f, "else"),
+            Token::RepeatSym => write!(f, "repeat"),
+            Token::UntilSym => write!(f, "until"),
+            Token::ForSym => write!(f, "for"),
+            Token::ToSym => write!(f, "to"),
+            Token::DowntoSym => write!(f, "downto"),
+            Token::BySym => write!(f, "by"),
+            Token::CaseSym => write!(f, "case"),
+            Token::OfSym => write!(f, "of"),
+            Token::AndSym => write!(f, "and"),
+            Token::OrSym => write!(f, "or"),
+            Token::NotSym => write!(f, "not"),
             Token::WhileSym => write!(f, "while"),
             Token::DoSym => write!(f, "do"),
             Token::CallSym => write!(f, "call"),
@@ -0,0 +1,94 @@
+//Trace of the p-machine, one line per executed instruction like in Wirth's teaching
+//interpreter: the instruction, then the registers and the top of stack after it ran.
+//
+//    8  lod  1    3   p    9  b    1  t    5   168
+//
+//With `stack` the whole current frame from b to t is shown instead of the top only.
+//The JSON format has the same fields, one object per line.
+
+use crate::defs::{fct_as_i32, Program, MNEMONICS};
+use crate::interpreter::{step, InterpreterConfig, Machine, RuntimeError};
+use crate::io::Io;
+use crate::listing::instr_listing;
+use std::io::{self, Write};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+pub struct Tracer<'a> {
+    pub out: &'a mut dyn Write,
+    pub format: TraceFormat,
+    pub stack: bool, //Current frame instead of top of stack
+}
+
+//Values shown after the instruction: top of stack or the frame from b to t
+fn window<'a>(m: &'a Machine, stack: bool) -> &'a [i32] {
+    let top = m.t.min(m.s.len() - 1);
+    if top == 0 {
+        return &[];
+    }
+    let from = if stack {
+        (m.b.max(1) as usize).min(top)
+    } else {
+        top
+    };
+    &m.s[from..=top]
+}
+
+fn trace_line(m: &Machine, pc: i32, tr: &mut Tracer) -> io::Result<()> {
+    let instr = m.prog.code[pc as usize];
+    let values = window(m, tr.stack);
+    match tr.format {
+        TraceFormat::Text => {
+            let mut line = format!(
+                "{}   p {:>4}  b {:>4}  t {:>4}  ",
+                instr_listing(pc as usize, &instr),
+                m.p,
+                m.b,
+                m.t
+            );
+            for v in values {
+                line.push_str(&format!(" {v}"));
+            }
+            writeln!(tr.out, "{}", line.trim_end())
+        }
+        TraceFormat::Json => {
+            let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+            let key = if tr.stack { "stack" } else { "top" };
+            let shown = match (tr.stack, values.first()) {
+                (true, _) => format!("[{}]", values.join(",")),
+                (false, Some(v)) => v.clone(),
+                (false, None) => "null".to_owned(),
+            };
+            writeln!(
+                tr.out,
+                "{{\"pc\":{pc},\"fct\":\"{}\",\"level\":{},\"adr\":{},\"p\":{},\"b\":{},\"t\":{},\"{key}\":{shown}}}",
+                MNEMONICS[fct_as_i32(instr.fct) as usize],
+                instr.level,
+                instr.adr,
+                m.p,
+                m.b,
+                m.t
+            )
+        }
+    }
+}
+
+//Interpret the program and trace every instruction it executes
+pub fn interpret_traced(
+    prog: &Program,
+    config: &InterpreterConfig,
+    io: &mut dyn Io,
+    tr: &mut Tracer,
+) -> Result<(), RuntimeError> {
+    let mut m = Machine::new(prog, config)?;
+    while !m.halted {
+        let pc = m.p;
+        step(&mut m, io)?;
+        trace_line(&m, pc, tr).map_err(|e| RuntimeError::Io(pc, e))?;
+    }
+    tr.out.flush().map_err(|e| RuntimeError::Io(m.p, e))
+}